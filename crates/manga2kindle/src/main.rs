@@ -10,10 +10,15 @@ use iced::{Background, Color, Element, Length, Padding, Subscription, Task, Them
 use iced_aw::card;
 use image::imageops::FilterType;
 use image::{DynamicImage, GenericImageView, ImageFormat};
-use mobi::mobi_writer::MobiWriter;
+use mobi::apnx::Apnx;
+use mobi::error::MobiError;
+use mobi::mobi::MOBI;
+use mobi::mobi_writer::{MobiWriter, UniqueId};
+use palm_database::lazy::LazyPdb;
 use reqwest::Client;
 use std::collections::HashMap;
-use std::io::Cursor;
+use std::io::{BufWriter, Cursor, Write};
+use std::path::PathBuf;
 use std::sync::Arc;
 
 const IMAGE_WIDTH: f32 = 128.0;
@@ -65,6 +70,7 @@ enum Error {
     IOFailed(Arc<std::io::Error>),
     JoinFailed(Arc<tokio::task::JoinError>),
     ImageFailed(Arc<image::ImageError>),
+    MobiFailed(Arc<MobiError>),
     GenericError(String),
 }
 
@@ -144,7 +150,7 @@ struct DownloadImage {
     name: String,
     width: u32,
     height: u32,
-    bytes: Vec<u8>,
+    path: PathBuf,
 }
 
 struct App {
@@ -511,6 +517,7 @@ impl App {
 
     fn write_manga(&mut self) -> Result<(), Error> {
         if let Some(active_download) = self.active_download.as_mut() {
+            let _download_dir = DownloadDir(download_dir(&active_download.hash));
             let selected_manga = self.selected_manga.as_ref().unwrap();
             let title = selected_manga.title.clone();
             let cover_image = selected_manga
@@ -520,25 +527,33 @@ impl App {
 
             let mut html = "<html><head></head><body>".to_owned();
             let mut writer = MobiWriter::new(title.clone());
+            // Regenerated chapters stay byte identical and keep their read position.
+            writer.set_unique_id(UniqueId::ContentHash);
             writer.add_image(make_cover(cover_image)?);
             for (i, k) in active_download.images.drain(..).enumerate() {
                 let download_image = active_download
                     .downloaded_images
                     .remove(&k)
                     .expect("Failed to find image");
-                writer.add_image(download_image.bytes);
+                writer.add_image_file(download_image.path);
                 html += format!("<p height=\"0pt\" width=\"0pt\" align=\"center\"><img recindex=\"{:05}\" align=\"baseline\" width=\"{}\" height=\"{}\"></img></p><mbp:pagebreak/>", i+2, download_image.width, download_image.height).as_str();
             }
 
             html += "</body></html>";
             writer.set_content(html);
-            std::fs::write(
-                format!(
-                    "{}.{}.{}.mobi",
-                    title, active_download.volume, active_download.chapter
-                ),
-                writer.to_bytes()?,
-            )?;
+            let name = format!(
+                "{}.{}.{}",
+                title, active_download.volume, active_download.chapter
+            );
+            let file = std::fs::File::create(format!("{name}.mobi"))?;
+            writer.write_to(BufWriter::new(file))?.flush()?;
+
+            // Page numbers on the Kindle, one page per image. Only the text
+            // records are read back.
+            let pdb = LazyPdb::new(std::fs::File::open(format!("{name}.mobi"))?)
+                .map_err(MobiError::from)?;
+            let apnx = Apnx::from_pagebreaks(&MOBI::from_source(pdb)?)?;
+            std::fs::write(format!("{name}.apnx"), apnx.to_bytes()?)?;
 
             Ok(())
         } else {
@@ -659,12 +674,32 @@ async fn download_manga(
     Ok((response, (volume, chapter)))
 }
 
+/// Downloaded pages are kept on disk until the book is written, so memory use
+/// doesn't grow with the number of pages. The directory includes the process
+/// ID, so other instances downloading the same chapter don't share it.
+fn download_dir(hash: &str) -> PathBuf {
+    std::env::temp_dir()
+        .join("manga2kindle")
+        .join(format!("{}-{hash}", std::process::id()))
+}
+
+/// Removes a download directory when dropped, so the spilled pages are
+/// cleaned up even if writing the chapter fails.
+struct DownloadDir(PathBuf);
+
+impl Drop for DownloadDir {
+    fn drop(&mut self) {
+        _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
 fn download_manga_images(download: &Download) -> Subscription<Message> {
     let client = get_client();
 
     let base_url = download.base_url.to_owned();
     let hash = download.hash.to_owned();
     let data = download.images.to_owned();
+    let download_dir = download_dir(&download.hash);
 
     let downloads = stream::iter(data.clone())
         .map(move |file| {
@@ -672,6 +707,7 @@ fn download_manga_images(download: &Download) -> Subscription<Message> {
             let hash = hash.clone();
             let file = file.clone();
             let client = client.clone();
+            let download_dir = download_dir.clone();
 
             async move {
                 match client
@@ -685,22 +721,24 @@ fn download_manga_images(download: &Download) -> Subscription<Message> {
                             .await
                             .map_err(Into::into)
                             .and_then(|bytes| image::load_from_memory(&bytes).map_err(Into::into))
-                            .and_then(|image| {
+                            .and_then(|image| -> Result<DownloadImage, Error> {
                                 let mut bytes = Cursor::new(Vec::new());
                                 let (width, height) = get_adjusted_size(&image);
-                                match image
+                                image
                                     .grayscale()
                                     .resize(width, height, FilterType::Lanczos3)
-                                    .write_to(&mut bytes, ImageFormat::Jpeg)
-                                {
-                                    Ok(()) => Ok(DownloadImage {
-                                        name: file,
-                                        width,
-                                        height,
-                                        bytes: bytes.into_inner(),
-                                    }),
-                                    Err(e) => Err(e.into()),
-                                }
+                                    .write_to(&mut bytes, ImageFormat::Jpeg)?;
+
+                                std::fs::create_dir_all(&download_dir)?;
+                                let path = download_dir.join(&file);
+                                std::fs::write(&path, bytes.into_inner())?;
+
+                                Ok(DownloadImage {
+                                    name: file,
+                                    width,
+                                    height,
+                                    path,
+                                })
                             });
 
                         Message::ImageDownloaded(result)
@@ -799,8 +837,8 @@ impl From<image::ImageError> for Error {
     }
 }
 
-impl From<anyhow::Error> for Error {
-    fn from(value: anyhow::Error) -> Self {
-        Error::GenericError(value.to_string())
+impl From<MobiError> for Error {
+    fn from(err: MobiError) -> Error {
+        Error::MobiFailed(Arc::new(err))
     }
 }
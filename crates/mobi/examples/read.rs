@@ -1,9 +1,6 @@
-use anyhow::anyhow;
-use mobi::mobi::{MOBI, MOBIHeader, PalmDOCHeader};
-use palm_database::PDB;
+use mobi::mobi::MOBI;
 use std::fs::File;
-use std::io::{Read, Write};
-use std::path::PathBuf;
+use std::io::Write;
 use mobi::compression::palmdoc_decompress;
 
 fn main() -> anyhow::Result<()> {
@@ -11,6 +8,7 @@ fn main() -> anyhow::Result<()> {
     let mobi = MOBI::from_bytes(&mut data)?;
     eprintln!("{:#?}", mobi.palmdoc_header);
     eprintln!("{:#?}", mobi.header);
+    eprintln!("{} ({:?})", mobi.full_name()?, mobi.encoding());
 
     std::fs::create_dir("dump2")?;
 
@@ -21,7 +19,7 @@ fn main() -> anyhow::Result<()> {
 
         File::create(format!("dump2/record_{i}.bin"))?.write_all(&record_data)?;
         let new_text = palmdoc_decompress(&record_data);
-        File::create(format!("dump2/record_{i}.txt"))?.write(&new_text)?;
+        File::create(format!("dump2/record_{i}.txt"))?.write_all(&new_text)?;
         str.extend_from_slice(&new_text);
     }
 
@@ -35,7 +33,9 @@ fn main() -> anyhow::Result<()> {
         File::create(format!("dump2/record_{i}.bin"))?.write_all(&record_data)?;
     }
 
-    File::create("dump2/record.html".to_string())?.write(String::from_utf8_lossy(&str).as_bytes())?;
+    File::create("dump2/record.bin")?.write_all(&str)?;
+    File::create("dump2/record.txt")?.write_all(mobi.text()?.as_bytes())?;
+    File::create("dump2/record.html")?.write_all(mobi.html()?.as_bytes())?;
 
     Ok(())
 }
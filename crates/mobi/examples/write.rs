@@ -4,10 +4,6 @@ use mobi::mobi_writer::MobiWriter;
 use std::io::Cursor;
 
 fn main() -> anyhow::Result<()> {
-    // let mut mobi = MOBI::new("Perfect World");
-    // mobi.set_content("<html><head></head><body><p>Test</p></body></html>");
-    //
-    // std::fs::write("output.mobi", &mobi.to_bytes()?)?;
     let mut html = "<html><head></head><body>".to_owned();
     let mut writer = MobiWriter::new("Perfect World".to_owned());
     for (i, image) in std::fs::read_dir("download/b21864d6-d661-4702-a86e-4efd5ebdaecb")?.enumerate() {
@@ -0,0 +1,230 @@
+use crate::error::MobiError;
+use crate::exth_header::{ASIN, CDE_TYPE};
+use crate::links::{find_tag, is_tag, tag_end};
+use crate::mobi::MOBI;
+use byyte::be::{ByteReader, ByteWriter};
+use palm_database::{RecordSource, TextEncoding};
+use std::io::Write;
+
+const VERSION: u32 = 0x0001_0001;
+/// Visible characters per page for [`Apnx::from_text_length`], about a
+/// printed paperback page.
+pub const DEFAULT_CHARS_PER_PAGE: usize = 1800;
+
+/// An `.apnx` page map, which Kindles read from next to a sideloaded book to
+/// show page numbers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Apnx {
+    pub content_guid: String,
+    pub asin: String,
+    pub cde_type: String,
+    /// Where each page starts, as byte offsets into the uncompressed text.
+    pub pages: Vec<u32>,
+}
+
+impl Apnx {
+    fn for_book<P: RecordSource>(mobi: &MOBI<P>, pages: Vec<u32>) -> Self {
+        Self {
+            content_guid: format!("{:08x}", mobi.header.unique_id),
+            asin: mobi.exth_string(ASIN).unwrap_or_default(),
+            cde_type: mobi
+                .exth_string(CDE_TYPE)
+                .unwrap_or_else(|| "EBOK".to_owned()),
+            pages,
+        }
+    }
+
+    /// A page for every `<mbp:pagebreak/>`, e.g. one per image of a manga
+    /// chapter. Pages without text or images, like the end of the body after
+    /// the last page break, are left out.
+    pub fn from_pagebreaks<P: RecordSource>(mobi: &MOBI<P>) -> Result<Self, MobiError> {
+        let text = mobi.raw_text()?;
+        let mut starts = vec![0];
+        let mut i = 0;
+        while let Some(start) = find_tag(&text, i, b"mbp:pagebreak") {
+            i = tag_end(&text, start).unwrap_or(text.len());
+            starts.push(i);
+        }
+        starts.push(text.len());
+
+        let pages = starts
+            .windows(2)
+            .filter(|page| has_content(&text[page[0]..page[1]]))
+            .map(|page| page[0] as u32)
+            .collect();
+        Ok(Self::for_book(mobi, pages))
+    }
+
+    /// A page every `chars_per_page` characters of text outside of tags, for
+    /// books without page breaks.
+    pub fn from_text_length<P: RecordSource>(
+        mobi: &MOBI<P>,
+        chars_per_page: usize,
+    ) -> Result<Self, MobiError> {
+        let text = mobi.raw_text()?;
+        let utf8 = mobi.encoding() == TextEncoding::Utf8;
+        let mut pages = vec![0];
+        let mut chars = 0;
+        let mut i = 0;
+        while i < text.len() {
+            if text[i] == b'<' {
+                i = tag_end(&text, i).unwrap_or(text.len());
+                continue;
+            }
+            // Continuation bytes of UTF-8 characters aren't counted.
+            if !utf8 || text[i] & 0xC0 != 0x80 {
+                if chars == chars_per_page.max(1) {
+                    pages.push(i as u32);
+                    chars = 0;
+                }
+                chars += 1;
+            }
+            i += 1;
+        }
+        Ok(Self::for_book(mobi, pages))
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, MobiError> {
+        let mut reader = data;
+        let version = reader.read_u32()?;
+        if version != VERSION {
+            return Err(MobiError::InvalidData(format!(
+                "Unsupported APNX version {version:#010x}"
+            )));
+        }
+        let pages_offset = reader.read_u32()? as usize;
+        let content_header_length = reader.read_u32()? as usize;
+        let content_header = reader
+            .get(..content_header_length)
+            .ok_or(MobiError::Truncated)?;
+        let content_header = String::from_utf8_lossy(content_header);
+
+        let mut reader = data.get(pages_offset..).ok_or(MobiError::Truncated)?;
+        reader.read_u16()?; // Version
+        let page_header_length = reader.read_u16()? as usize;
+        let page_count = reader.read_u16()?;
+        reader.read_u16()?; // Bits per offset, always 32
+        reader = reader
+            .get(page_header_length..)
+            .ok_or(MobiError::Truncated)?;
+        let pages = (0..page_count)
+            .map(|_| reader.read_u32())
+            .collect::<Result<_, _>>()?;
+
+        let field = |name: &str| json_string(&content_header, name).unwrap_or_default();
+        Ok(Self {
+            content_guid: field("contentGuid"),
+            asin: field("asin"),
+            cde_type: field("cdeType"),
+            pages,
+        })
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, MobiError> {
+        if self.pages.len() > u16::MAX as usize {
+            return Err(MobiError::LimitExceeded {
+                what: "number of pages",
+                value: self.pages.len() as u64,
+                limit: u16::MAX as u64,
+            });
+        }
+        let content_header = format!(
+            r#"{{"contentGuid":"{}","asin":"{}","cdeType":"{}","fileRevisionId":"1"}}"#,
+            json_escape(&self.content_guid),
+            json_escape(&self.asin),
+            json_escape(&self.cde_type)
+        );
+        let page_header = format!(
+            r#"{{"asin":"{}","pageMap":"(1,a,1)"}}"#,
+            json_escape(&self.asin)
+        );
+
+        let mut data = vec![];
+        data.write_u32(VERSION)?;
+        data.write_u32(12 + content_header.len() as u32)?;
+        data.write_u32(content_header.len() as u32)?;
+        data.write_all(content_header.as_bytes())?;
+        data.write_u16(1)?;
+        data.write_u16(page_header.len() as u16)?;
+        data.write_u16(self.pages.len() as u16)?;
+        data.write_u16(32)?;
+        data.write_all(page_header.as_bytes())?;
+        for &page in &self.pages {
+            data.write_u32(page)?;
+        }
+        Ok(data)
+    }
+}
+
+/// Whether a piece of the text shows anything, i.e. has text outside of tags
+/// or an image.
+fn has_content(text: &[u8]) -> bool {
+    let mut i = 0;
+    while i < text.len() {
+        if text[i] == b'<' {
+            if is_tag(text, i, b"img") {
+                return true;
+            }
+            i = tag_end(text, i).unwrap_or(text.len());
+        } else if text[i].is_ascii_whitespace() {
+            i += 1;
+        } else {
+            return true;
+        }
+    }
+    false
+}
+
+fn json_escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Reads a string field from the flat JSON object in the APNX headers.
+fn json_string(json: &str, name: &str) -> Option<String> {
+    let start = json.find(&format!("\"{name}\":\""))? + name.len() + 4;
+    let mut value = String::new();
+    let mut chars = json[start..].chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => return Some(value),
+            '\\' => value.push(chars.next()?),
+            c => value.push(c),
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mobi_writer::MobiWriter;
+    use std::io::Cursor;
+
+    fn test_book(html: &str) -> MOBI {
+        let mut writer = MobiWriter::new("Test".to_owned());
+        writer.set_content(html.to_owned());
+        MOBI::from_bytes(&mut Cursor::new(writer.to_bytes().unwrap())).unwrap()
+    }
+
+    #[test]
+    fn test_apnx_from_pagebreaks() {
+        let page = r#"<p><img recindex="00001"></p><mbp:pagebreak/>"#;
+        let html = format!("<html><body>{page}{page}{page}</body></html>");
+        let mobi = test_book(&html);
+        let apnx = Apnx::from_pagebreaks(&mobi).unwrap();
+
+        let first = page.len() as u32 + 12;
+        assert_eq!(apnx.pages, [0, first, first + page.len() as u32]);
+        assert_eq!(apnx.content_guid, format!("{:08x}", mobi.header.unique_id));
+        assert_eq!(apnx.cde_type, "EBOK");
+        assert_eq!(Apnx::from_bytes(&apnx.to_bytes().unwrap()).unwrap(), apnx);
+    }
+
+    #[test]
+    fn test_apnx_from_text_length() {
+        let html = format!("<p>{}</p><p>{}</p>", "a".repeat(10), "é".repeat(15));
+        let apnx = Apnx::from_text_length(&test_book(&html), 10).unwrap();
+        // The second page starts at the first é, the third after ten of them.
+        assert_eq!(apnx.pages, [0, 20, 40]);
+    }
+}
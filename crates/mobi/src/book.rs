@@ -0,0 +1,432 @@
+use crate::error::MobiError;
+use crate::exth_header::{
+    AUTHOR, COVER_OFFSET, DESCRIPTION, EXTHHeader, EXTHRecord, ISBN, LANGUAGE, PUBLISHER,
+    PUBLISHING_DATE, SUBJECT, THUMB_OFFSET, UPDATED_TITLE,
+};
+use crate::limits::ParseLimits;
+use crate::links::{escape_html, find_tag, parse_attributes, tag_end, unescape_html};
+use crate::mobi::MOBI;
+use crate::mobi_header::NULL_INDEX;
+use crate::mobi_writer::{EOF_RECORD, ImageSource, UniqueId, write_book};
+use crate::validate::is_image;
+use chrono::NaiveDateTime;
+use palm_database::{RecordSource, TextEncoding};
+use std::collections::HashMap;
+use std::io::{Read, Seek, Write};
+
+const TOC_ID: &str = "mobi-toc";
+const TOC_END: &str = "</div><mbp:pagebreak/>";
+/// Marks a guide added for the table of contents, so reading removes it
+/// again while keeping guides that were part of the HTML.
+const TOC_GUIDE: &str = r#"<guide id="mobi-toc-guide">"#;
+
+/// Book metadata, stored in the EXTH header apart from the title, which is
+/// also the full name.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Metadata {
+    pub title: String,
+    pub authors: Vec<String>,
+    pub publisher: Option<String>,
+    pub description: Option<String>,
+    pub isbn: Option<String>,
+    pub subjects: Vec<String>,
+    /// The publishing date as written, usually ISO 8601.
+    pub published: Option<String>,
+    pub language: Option<String>,
+    /// Index of the cover in [`MobiBook::images`].
+    pub cover: Option<usize>,
+    pub unique_id: UniqueId,
+    /// Used as the creation and modification time. Without one the time from
+    /// `SOURCE_DATE_EPOCH` is used, or the timestamps are left unset.
+    pub timestamp: Option<NaiveDateTime>,
+}
+
+impl Metadata {
+    /// Strings are written in `encoding`, with `?` for characters it can't
+    /// represent.
+    pub fn to_exth(&self, encoding: TextEncoding) -> EXTHHeader {
+        let mut exth = EXTHHeader::new();
+        let mut push = |type_: u32, data: Vec<u8>| {
+            exth.records.push(EXTHRecord { type_, data });
+        };
+        for author in &self.authors {
+            push(AUTHOR, encoding.encode_lossy(author));
+        }
+        for (type_, value) in [
+            (PUBLISHER, &self.publisher),
+            (DESCRIPTION, &self.description),
+            (ISBN, &self.isbn),
+            (PUBLISHING_DATE, &self.published),
+            (LANGUAGE, &self.language),
+        ] {
+            if let Some(value) = value {
+                push(type_, encoding.encode_lossy(value));
+            }
+        }
+        for subject in &self.subjects {
+            push(SUBJECT, encoding.encode_lossy(subject));
+        }
+        if let Some(cover) = self.cover {
+            push(COVER_OFFSET, (cover as u32).to_be_bytes().to_vec());
+            push(THUMB_OFFSET, (cover as u32).to_be_bytes().to_vec());
+        }
+        exth
+    }
+
+    fn from_exth(exth: &EXTHHeader, encoding: TextEncoding, full_name: String) -> Self {
+        let all = |type_: u32| {
+            exth.records
+                .iter()
+                .filter(|record| record.type_ == type_)
+                .map(|record| encoding.decode(&record.data))
+                .collect()
+        };
+        let get = |type_: u32| exth.get(type_).map(|data| encoding.decode(data));
+        Self {
+            title: get(UPDATED_TITLE).unwrap_or(full_name),
+            authors: all(AUTHOR),
+            publisher: get(PUBLISHER),
+            description: get(DESCRIPTION),
+            isbn: get(ISBN),
+            subjects: all(SUBJECT),
+            published: get(PUBLISHING_DATE),
+            language: get(LANGUAGE),
+            cover: exth
+                .get_u32(COVER_OFFSET)
+                .filter(|&offset| offset != NULL_INDEX)
+                .map(|offset| offset as usize),
+            ..Self::default()
+        }
+    }
+}
+
+/// A link in the table of contents.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TocEntry {
+    pub title: String,
+    /// The `id` of the element the entry links to.
+    pub anchor: String,
+}
+
+/// A book as its parts. [`MobiBook::from_mobi`] reads one from any MOBI file
+/// and [`MobiBook::to_bytes`] writes one, so that a book that was read can be
+/// written again and vice versa.
+#[derive(Debug, Clone)]
+pub struct MobiBook {
+    pub metadata: Metadata,
+    /// Links of the form `href="#name"` are resolved to `filepos` offsets of
+    /// the tag with `id="name"` when the book is written.
+    pub html: String,
+    /// Image records, referenced from the HTML by `recindex`, starting at 1.
+    pub images: Vec<ImageSource>,
+    /// Other records, such as fonts, written after the images.
+    pub resources: Vec<Vec<u8>>,
+    /// Written as a page at the start of the book, with a guide reference.
+    pub toc: Vec<TocEntry>,
+    /// 1 for uncompressed text, 2 for PalmDOC compression.
+    pub compression: u16,
+    /// The encoding of the text and metadata. Old devices only support
+    /// [`TextEncoding::Cp1252`], which can't represent every character.
+    pub encoding: TextEncoding,
+}
+
+impl MobiBook {
+    pub fn new(title: impl Into<String>) -> Self {
+        Self {
+            metadata: Metadata {
+                title: title.into(),
+                ..Metadata::default()
+            },
+            html: String::new(),
+            images: vec![],
+            resources: vec![],
+            toc: vec![],
+            compression: 2,
+            encoding: TextEncoding::Utf8,
+        }
+    }
+
+    /// Reads a book with the default [`ParseLimits`].
+    pub fn from_bytes<R: Read + Seek>(reader: &mut R) -> Result<Self, MobiError> {
+        Self::from_mobi(&MOBI::from_bytes(reader)?)
+    }
+
+    pub fn from_bytes_with_limits<R: Read + Seek>(
+        reader: &mut R,
+        limits: &ParseLimits,
+    ) -> Result<Self, MobiError> {
+        Self::from_mobi(&MOBI::from_bytes_with_limits(reader, limits)?)
+    }
+
+    /// Reads the metadata, text and records of a book. The unique ID and
+    /// creation time are kept, so writing the book again gives the same
+    /// headers.
+    pub fn from_mobi<P: RecordSource>(mobi: &MOBI<P>) -> Result<Self, MobiError> {
+        let exth = mobi.exth.clone().unwrap_or_default();
+        let mut metadata = Metadata::from_exth(&exth, mobi.encoding(), mobi.full_name()?);
+        metadata.unique_id = UniqueId::Fixed(mobi.header.unique_id);
+        metadata.timestamp = mobi.pdb.header().creation_time.map(|time| time.datetime);
+
+        let first_image = [
+            mobi.header.first_image_index,
+            mobi.header.first_non_book_index,
+        ]
+        .into_iter()
+        .find(|&index| index != NULL_INDEX)
+        .unwrap_or(u32::MAX)
+        .max(mobi.palmdoc_header.record_count as u32 + 1);
+        let mut images = vec![];
+        let mut resources = vec![];
+        // New image numbers by `recindex`, which counts every record from the
+        // first image.
+        let mut numbers = HashMap::new();
+        for i in first_image..mobi.pdb.header().number_of_records as u32 {
+            let record = mobi.pdb.record_data(i as u16)?;
+            // KF8 books continue after a boundary record, which is not read.
+            if record.starts_with(b"BOUN") {
+                break;
+            }
+            if is_special(&record) {
+                continue;
+            }
+            if is_image(&record) {
+                images.push(ImageSource::Bytes(record.into_owned()));
+                numbers.insert((i - first_image + 1) as usize, images.len());
+            } else {
+                resources.push(record.into_owned());
+            }
+        }
+        metadata.cover = metadata
+            .cover
+            .and_then(|cover| numbers.get(&(cover + 1)))
+            .map(|number| number - 1);
+
+        let (html, toc) = extract_toc(renumber_images(mobi.html()?, &numbers));
+        Ok(Self {
+            metadata,
+            html,
+            images,
+            resources,
+            toc,
+            compression: mobi.palmdoc_header.compression,
+            encoding: mobi.encoding(),
+        })
+    }
+
+    /// The HTML with the table of contents inserted at the start of the body
+    /// and a guide reference to it, as it is written.
+    pub fn html_with_toc(&self) -> String {
+        if self.toc.is_empty() {
+            return self.html.clone();
+        }
+        let mut html = self.html.clone();
+
+        let mut toc = format!(r#"<div id="{TOC_ID}">"#);
+        for entry in &self.toc {
+            toc.push_str(&format!(
+                r##"<p><a href="#{}">{}</a></p>"##,
+                escape_html(&entry.anchor),
+                escape_html(&entry.title)
+            ));
+        }
+        toc.push_str(TOC_END);
+        let body = find_tag(html.as_bytes(), 0, b"body")
+            .and_then(|start| tag_end(html.as_bytes(), start))
+            .unwrap_or(0);
+        html.insert_str(body, &toc);
+
+        let reference =
+            format!(r##"<reference type="toc" title="Table of Contents" href="#{TOC_ID}"/>"##);
+        if let Some(guide) = find_tag(html.as_bytes(), 0, b"guide")
+            && let Some(end) = tag_end(html.as_bytes(), guide)
+        {
+            html.insert_str(end, &reference);
+        } else if let Some(head) = find_tag(html.as_bytes(), 0, b"/head") {
+            html.insert_str(head, &format!("{TOC_GUIDE}{reference}</guide>"));
+        }
+        html
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, MobiError> {
+        crate::mobi_writer::to_bytes(self)
+    }
+
+    /// Writes the book record by record, reading file backed images from disk
+    /// as they are needed.
+    pub fn write_to<W: Write + Seek>(&self, writer: W) -> Result<W, MobiError> {
+        write_book(self, writer)
+    }
+}
+
+/// Removes the table of contents written by [`MobiBook::html_with_toc`] from
+/// read HTML, along with its guide reference, and returns its
+/// entries. Other tables of contents are part of the text and left alone.
+fn extract_toc(mut html: String) -> (String, Vec<TocEntry>) {
+    let marker = format!(r#"<div id="{TOC_ID}">"#);
+    let Some(start) = html.find(&marker) else {
+        return (html, vec![]);
+    };
+    let Some(end) = html[start..].find(TOC_END).map(|end| start + end) else {
+        return (html, vec![]);
+    };
+
+    let mut toc = vec![];
+    let block = &html.as_bytes()[start + marker.len()..end];
+    let mut i = 0;
+    while let Some(tag_start) = block[i..].iter().position(|&b| b == b'<').map(|p| i + p) {
+        let tag_end_ = tag_end(block, tag_start).unwrap_or(block.len());
+        let href = parse_attributes(&block[tag_start..tag_end_])
+            .into_iter()
+            .find(|attribute| attribute.name == "href");
+        if let Some(href) = href.filter(|href| href.value.starts_with('#')) {
+            let close = block[tag_end_..]
+                .windows(4)
+                .position(|window| window == b"</a>")
+                .map_or(block.len(), |p| tag_end_ + p);
+            toc.push(TocEntry {
+                title: unescape_html(&String::from_utf8_lossy(&block[tag_end_..close])),
+                anchor: href.value[1..].to_owned(),
+            });
+            i = close;
+        } else {
+            i = tag_end_;
+        }
+    }
+
+    html.replace_range(start..end + TOC_END.len(), "");
+
+    let reference =
+        format!(r##"<reference type="toc" title="Table of Contents" href="#{TOC_ID}"/>"##);
+    if let Some(reference_start) = html.find(&reference) {
+        html.replace_range(reference_start..reference_start + reference.len(), "");
+        html = html.replacen(&format!("{TOC_GUIDE}</guide>"), "", 1);
+    }
+    (html, toc)
+}
+
+/// Rewrites `recindex` attributes to the new image numbers, for images that
+/// moved up because other records came before them.
+fn renumber_images(html: String, numbers: &HashMap<usize, usize>) -> String {
+    if numbers.iter().all(|(old, new)| old == new) {
+        return html;
+    }
+    let bytes = html.as_bytes();
+    let mut output = String::with_capacity(html.len());
+    let mut last = 0;
+    let mut i = 0;
+    while let Some(start) = bytes[i..].iter().position(|&b| b == b'<').map(|p| i + p) {
+        let end = tag_end(bytes, start).unwrap_or(bytes.len());
+        for attribute in parse_attributes(&bytes[start..end]) {
+            if attribute.name != "recindex" {
+                continue;
+            }
+            let Ok(old) = attribute.value.trim().parse::<usize>() else {
+                continue;
+            };
+            if let Some(&new) = numbers.get(&old).filter(|&&new| new != old) {
+                output.push_str(&html[last..start + attribute.range.start]);
+                output.push_str(&format!(r#"recindex="{new:05}""#));
+                last = start + attribute.range.end;
+            }
+        }
+        i = end;
+    }
+    output.push_str(&html[last..]);
+    output
+}
+
+fn is_special(record: &[u8]) -> bool {
+    record == EOF_RECORD
+        || [&b"FLIS"[..], b"FCIS", b"SRCS", b"CMET", b"DATP", b"INDX"]
+            .iter()
+            .any(|magic| record.starts_with(magic))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_book_round_trip() {
+        let mut book = MobiBook::new("Round Trip");
+        book.metadata.authors = vec!["First".to_owned(), "Second".to_owned()];
+        book.metadata.publisher = Some("Publisher".to_owned());
+        book.metadata.language = Some("en".to_owned());
+        book.metadata.cover = Some(1);
+        book.metadata.unique_id = UniqueId::Fixed(7);
+        book.html = r#"<html><head><guide></guide></head><body><h1 id="one">One & two</h1><p>Text</p><img recindex="00001"><h1 id="two">Two</h1></body></html>"#.to_owned();
+        book.images = vec![
+            ImageSource::Bytes(vec![0xFF, 0xD8, 0xFF, 0xE0]),
+            ImageSource::Bytes(b"\x89PNG cover".to_vec()),
+        ];
+        book.resources = vec![b"font data".to_vec()];
+        book.toc = vec![
+            TocEntry {
+                title: "One & two".to_owned(),
+                anchor: "one".to_owned(),
+            },
+            TocEntry {
+                title: "Two".to_owned(),
+                anchor: "two".to_owned(),
+            },
+        ];
+
+        let bytes = book.to_bytes().unwrap();
+        let read = MobiBook::from_bytes(&mut Cursor::new(&bytes)).unwrap();
+        assert_eq!(read.metadata.title, "Round Trip");
+        assert_eq!(read.metadata.authors, book.metadata.authors);
+        assert_eq!(read.metadata.publisher.as_deref(), Some("Publisher"));
+        assert_eq!(read.metadata.language.as_deref(), Some("en"));
+        assert_eq!(read.metadata.cover, Some(1));
+        assert_eq!(read.metadata.unique_id, UniqueId::Fixed(7));
+        assert_eq!(read.images, book.images);
+        assert_eq!(read.resources, book.resources);
+        assert_eq!(read.toc, book.toc);
+        assert_eq!(read.html, book.html);
+
+        let again = MobiBook::from_bytes(&mut Cursor::new(read.to_bytes().unwrap())).unwrap();
+        assert_eq!(again.metadata, read.metadata);
+        assert_eq!(again.to_bytes().unwrap(), read.to_bytes().unwrap());
+
+        // A guide added for the table of contents is removed again.
+        book.html =
+            r#"<html><head></head><body><h1 id="one">One</h1><h1 id="two">Two</h1></body></html>"#
+                .to_owned();
+        let read = MobiBook::from_bytes(&mut Cursor::new(book.to_bytes().unwrap())).unwrap();
+        assert_eq!(read.html, book.html);
+        assert_eq!(read.toc, book.toc);
+    }
+
+    #[test]
+    fn test_images_around_resource() {
+        let mut book = MobiBook::new("Images");
+        book.metadata.cover = Some(2);
+        book.html = r#"<img recindex="00001"><img recindex="00003">"#.to_owned();
+        // Written as image, font, image.
+        book.images = vec![
+            ImageSource::Bytes(vec![0xFF, 0xD8, 0xFF, 0xE0]),
+            ImageSource::Bytes(b"font data".to_vec()),
+            ImageSource::Bytes(b"\x89PNG cover".to_vec()),
+        ];
+
+        let read = MobiBook::from_bytes(&mut Cursor::new(book.to_bytes().unwrap())).unwrap();
+        assert_eq!(
+            read.images,
+            [book.images[0].clone(), book.images[2].clone()]
+        );
+        assert_eq!(read.resources, [b"font data".to_vec()]);
+        assert_eq!(read.html, r#"<img recindex="00001"><img recindex="00002">"#);
+        assert_eq!(read.metadata.cover, Some(1));
+    }
+
+    #[test]
+    fn test_extract_toc_keeps_stray_anchor() {
+        let html = format!(
+            r##"<a id="></a><div id="{TOC_ID}"><p><a href="#x">X</a></p>{TOC_END}<p>Text</p>"##
+        );
+        let (html, toc) = extract_toc(html);
+        assert_eq!(html, r#"<a id="></a><p>Text</p>"#);
+        assert_eq!(toc.len(), 1);
+    }
+}
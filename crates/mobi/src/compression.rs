@@ -1,8 +1,42 @@
+use crate::error::MobiError;
+
 pub fn palmdoc_decompress(data: &[u8]) -> Vec<u8> {
     let mut out: Vec<u8> = Vec::with_capacity(data.len() * 2);
+    decompress_into(data, &mut out, usize::MAX);
+    out
+}
+
+/// Like [`palmdoc_decompress`], but fails once the output grows past `limit`
+/// bytes instead of expanding a malicious record without bound.
+pub fn palmdoc_decompress_with_limit(data: &[u8], limit: usize) -> Result<Vec<u8>, MobiError> {
+    let mut out: Vec<u8> = Vec::with_capacity((data.len() * 2).min(limit));
+    if decompress_into(data, &mut out, limit) {
+        Ok(out)
+    } else {
+        Err(decompressed_size_exceeded(out.len(), limit))
+    }
+}
+
+fn decompressed_size_exceeded(size: usize, limit: usize) -> MobiError {
+    MobiError::LimitExceeded {
+        what: "decompressed size",
+        value: size as u64,
+        limit: limit as u64,
+    }
+}
+
+/// Decompresses `data`, appending to `out`. Returns false if decompression
+/// stopped because `out` grew past `limit` bytes.
+fn decompress_into(data: &[u8], out: &mut Vec<u8>, limit: usize) -> bool {
+    // Back references may not reach into data decompressed before this record.
+    let base = out.len();
     let mut i = 0usize;
 
     while i < data.len() {
+        if out.len() > limit {
+            return false;
+        }
+
         let frame = data[i];
         i += 1;
 
@@ -41,25 +75,88 @@ pub fn palmdoc_decompress(data: &[u8]) -> Vec<u8> {
 
                 let concat = ((frame as u16) << 8) | (second as u16);
                 let distance = ((concat >> 3) & 0x07FF) as usize; // 11 bits
-                let length = ((concat & 0x07) + 3) as usize;       // 3..10
+                let length = ((concat & 0x07) + 3) as usize; // 3..10
 
                 // invalid backreference -> abort to avoid panic
-                if distance == 0 || distance > out.len() {
+                if distance == 0 || distance > out.len() - base {
                     break;
                 }
 
                 // start position in output to copy from
-                let mut src = out.len() - distance;
-                for _ in 0..length {
+                let start = out.len() - distance;
+                for src in start..start + length {
                     // reading from `out[src]` is safe because `out` grows as we push,
                     // and `src` will always be < current length at read time.
                     let b = out[src];
                     out.push(b);
-                    src += 1;
                 }
             }
         }
     }
 
-    out
+    out.len() <= limit
+}
+
+/// Decompresses the text records of a book into its text, which is cut to
+/// `text_length` bytes and may not grow past `limit` bytes.
+pub(crate) fn decompress_text<I, B>(
+    compression: u16,
+    text_length: u32,
+    records: I,
+    limit: usize,
+) -> Result<Vec<u8>, MobiError>
+where
+    I: IntoIterator<Item = Result<B, MobiError>>,
+    B: AsRef<[u8]>,
+{
+    if text_length as usize > limit {
+        return Err(decompressed_size_exceeded(text_length as usize, limit));
+    }
+
+    let mut text = Vec::with_capacity(text_length as usize);
+    for record in records {
+        let record = record?;
+        let record = record.as_ref();
+        match compression {
+            1 => {
+                if text.len() + record.len() > limit {
+                    return Err(decompressed_size_exceeded(text.len() + record.len(), limit));
+                }
+                text.extend_from_slice(record);
+            }
+            2 => {
+                if !decompress_into(record, &mut text, limit) {
+                    return Err(decompressed_size_exceeded(text.len(), limit));
+                }
+            }
+            compression => return Err(MobiError::UnsupportedCompression(compression)),
+        }
+    }
+    text.truncate(text_length as usize);
+
+    Ok(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decompress_limit() {
+        // A literal followed by back references repeating it 10 bytes at a time.
+        let mut data = vec![b'a'];
+        for _ in 0..100 {
+            data.extend_from_slice(&[0x80, 0x0F]);
+        }
+
+        assert_eq!(palmdoc_decompress(&data), vec![b'a'; 1001]);
+        assert_eq!(
+            palmdoc_decompress_with_limit(&data, 1001).unwrap().len(),
+            1001
+        );
+        assert!(matches!(
+            palmdoc_decompress_with_limit(&data, 500),
+            Err(MobiError::LimitExceeded { limit: 500, .. })
+        ));
+    }
 }
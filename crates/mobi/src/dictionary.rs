@@ -0,0 +1,592 @@
+use crate::error::{MobiError, check_magic};
+use crate::mobi::MOBI;
+use crate::mobi_header::NULL_INDEX;
+use byyte::be::ByteReader;
+use palm_database::{RecordSource, TextEncoding};
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// Tag of the orthographic index with the start of the definition.
+const TAG_START: u8 = 0x01;
+/// Tag of the orthographic index with the length of the definition.
+const TAG_LENGTH: u8 = 0x02;
+/// Tag of the orthographic index with the inflection groups of the headword.
+const TAG_INFLECTION_GROUPS: u8 = 0x2A;
+/// Tag of an inflection group with the rules of its inflections.
+const TAG_INFLECTION_RULES: u8 = 0x1A;
+
+/// A headword of a [`Dictionary`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub headword: String,
+    /// Where the definition is, as a byte range of the uncompressed text.
+    pub definition: Range<usize>,
+    /// The inflected forms of the headword, from the inflection index.
+    pub inflections: Vec<String>,
+}
+
+/// A Kindle dictionary, read from the orthographic index of a book and, if
+/// the book has one, its inflection index.
+#[derive(Debug, Clone)]
+pub struct Dictionary {
+    text: Vec<u8>,
+    encoding: TextEncoding,
+    entries: Vec<Entry>,
+    /// Entry indices by lowercase headword and inflected form.
+    words: HashMap<String, Vec<usize>>,
+}
+
+impl Dictionary {
+    /// Fails with [`MobiError::InvalidData`] if the book has no
+    /// orthographic index, i.e. isn't a dictionary.
+    pub fn from_mobi<P: RecordSource>(mobi: &MOBI<P>) -> Result<Self, MobiError> {
+        if mobi.header.orthographic_index == NULL_INDEX {
+            return Err(MobiError::InvalidData(
+                "The book has no orthographic index".to_owned(),
+            ));
+        }
+        let orthographic = Index::read(mobi, mobi.header.orthographic_index)?;
+        let inflections = match mobi.header.inflection_index {
+            NULL_INDEX => None,
+            index => Some(Index::read(mobi, index)?),
+        };
+        let encoding = orthographic.encoding.unwrap_or(mobi.encoding());
+
+        // Without lengths, definitions end where the next one starts.
+        let mut starts: Vec<usize> = orthographic
+            .entries
+            .iter()
+            .filter_map(|entry| entry.tags.get(&TAG_START)?.first())
+            .map(|&start| start as usize)
+            .collect();
+        starts.sort_unstable();
+        starts.dedup();
+
+        let mut dictionary = Self {
+            text: mobi.raw_text()?,
+            encoding: mobi.encoding(),
+            entries: vec![],
+            words: HashMap::new(),
+        };
+        for entry in &orthographic.entries {
+            let Some(&[start]) = entry.tags.get(&TAG_START).map(Vec::as_slice) else {
+                continue;
+            };
+            let start = start as usize;
+            let end = match entry.tags.get(&TAG_LENGTH).map(Vec::as_slice) {
+                Some(&[length]) => start + length as usize,
+                _ => starts
+                    .get(starts.partition_point(|&next| next <= start))
+                    .copied()
+                    .unwrap_or(dictionary.text.len()),
+            };
+            if start > end || end > dictionary.text.len() {
+                return Err(MobiError::InvalidData(format!(
+                    "Definition at {start}..{end} is outside the text"
+                )));
+            }
+
+            let mut forms = vec![];
+            if let (Some(inflections), Some(groups)) =
+                (&inflections, entry.tags.get(&TAG_INFLECTION_GROUPS))
+            {
+                for &group in groups {
+                    let group = inflections.entry(group)?;
+                    for &rule in group.tags.get(&TAG_INFLECTION_RULES).into_iter().flatten() {
+                        let rule = &inflections.entry(rule)?.label;
+                        if let Some(form) = apply_inflection_rule(&entry.label, rule) {
+                            forms.push(encoding.decode(&form));
+                        }
+                    }
+                }
+            }
+
+            dictionary.push(Entry {
+                headword: encoding.decode(&entry.label),
+                definition: start..end,
+                inflections: forms,
+            });
+        }
+        Ok(dictionary)
+    }
+
+    fn push(&mut self, entry: Entry) {
+        let index = self.entries.len();
+        for word in std::iter::once(&entry.headword).chain(&entry.inflections) {
+            let indices = self.words.entry(word.to_lowercase()).or_default();
+            if !indices.contains(&index) {
+                indices.push(index);
+            }
+        }
+        self.entries.push(entry);
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// The entries for a headword or an inflected form of one, ignoring case.
+    pub fn lookup(&self, word: &str) -> Vec<&Entry> {
+        self.words
+            .get(&word.to_lowercase())
+            .into_iter()
+            .flatten()
+            .map(|&index| &self.entries[index])
+            .collect()
+    }
+
+    /// The raw text of the definition of an entry, markup included. `None` if
+    /// the entry is outside the text, e.g. from another dictionary.
+    pub fn definition(&self, entry: &Entry) -> Option<String> {
+        let text = self.text.get(entry.definition.clone())?;
+        Some(self.encoding.decode(text))
+    }
+}
+
+struct IndexEntry {
+    label: Vec<u8>,
+    tags: HashMap<u8, Vec<u32>>,
+}
+
+/// An INDX index: a main record with the tag table, followed by the records
+/// with the entries.
+struct Index {
+    /// The encoding of the labels, `None` if the index doesn't say. Labels
+    /// mapped through an ORDT table are UTF-8.
+    encoding: Option<TextEncoding>,
+    entries: Vec<IndexEntry>,
+}
+
+/// The INDX header fields needed to read the entries.
+struct IndxHeader {
+    length: usize,
+    idxt_offset: usize,
+    count: u32,
+    code_page: u32,
+    /// The ORDT table mapping label characters to UTF-16 code units, and
+    /// whether the label characters are single bytes.
+    ordt: Option<(Vec<u16>, bool)>,
+}
+
+/// An entry of the TAGX table.
+struct Tag {
+    tag: u8,
+    values_per_entry: u8,
+    mask: u8,
+    end_of_control_byte: bool,
+}
+
+impl Index {
+    fn read<P: RecordSource>(mobi: &MOBI<P>, index: u32) -> Result<Self, MobiError> {
+        let record = |index: u32| -> Result<Vec<u8>, MobiError> {
+            let index = u16::try_from(index).map_err(|_| {
+                MobiError::InvalidData(format!("Index record {index} is out of range"))
+            })?;
+            Ok(mobi.pdb.record_data(index)?.into_owned())
+        };
+
+        let main = record(index)?;
+        let header = IndxHeader::from_bytes(&main)?;
+        let (control_bytes, tags) = read_tagx(&main, header.length)?;
+        let encoding = match header.ordt {
+            Some(_) => Some(TextEncoding::Utf8),
+            None => TextEncoding::from_code_page(header.code_page),
+        };
+
+        let mut entries = vec![];
+        for i in 1..=header.count {
+            let data = record(index + i)?;
+            let record_header = IndxHeader::from_bytes(&data)?;
+            let idxt = data
+                .get(record_header.idxt_offset..)
+                .ok_or(MobiError::Truncated)?;
+            check_magic("IDXT", idxt.get(..4).ok_or(MobiError::Truncated)?)?;
+            let mut reader = &idxt[4..];
+            let mut offsets = (0..record_header.count)
+                .map(|_| reader.read_u16().map(usize::from))
+                .collect::<Result<Vec<_>, _>>()?;
+            // The last entry ends at the IDXT, maybe followed by padding.
+            offsets.push(record_header.idxt_offset);
+
+            for offsets in offsets.windows(2) {
+                let entry = data
+                    .get(offsets[0]..offsets[1])
+                    .ok_or(MobiError::Truncated)?;
+                let (&length, rest) = entry.split_first().ok_or(MobiError::Truncated)?;
+                let label = rest.get(..length as usize).ok_or(MobiError::Truncated)?;
+                let label = match &header.ordt {
+                    Some((ordt, single_byte)) => map_ordt(label, ordt, *single_byte),
+                    None => label.to_vec(),
+                };
+                // Entries that are only a label, like inflection rules, have no
+                // control bytes.
+                let rest = &rest[length as usize..];
+                let tags = if rest.is_empty() {
+                    HashMap::new()
+                } else {
+                    read_tag_values(rest, control_bytes, &tags)?
+                };
+                entries.push(IndexEntry { label, tags });
+            }
+        }
+        Ok(Self { encoding, entries })
+    }
+
+    fn entry(&self, index: u32) -> Result<&IndexEntry, MobiError> {
+        self.entries
+            .get(index as usize)
+            .ok_or_else(|| MobiError::InvalidData(format!("Index entry {index} is out of range")))
+    }
+}
+
+impl IndxHeader {
+    fn from_bytes(data: &[u8]) -> Result<Self, MobiError> {
+        check_magic("INDX", data.get(..4).ok_or(MobiError::Truncated)?)?;
+        let field = |offset: usize| {
+            let mut reader = data.get(offset..).ok_or(MobiError::Truncated)?;
+            reader.read_u32().map_err(MobiError::from)
+        };
+        let code_page = field(0x1C)?;
+
+        let ordt_type = field(0xA4)?;
+        let ordt_entries = field(0xA8)? as usize;
+        let ordt = if code_page == 0xFDEA || ordt_entries > 0 {
+            // ORDT1 with single byte entries comes first, only ORDT2 is used.
+            let offset = field(0xB0)? as usize;
+            let table = data.get(offset..).ok_or(MobiError::Truncated)?;
+            check_magic("ORDT", table.get(..4).ok_or(MobiError::Truncated)?)?;
+            let mut reader = &table[4..];
+            let table = (0..ordt_entries)
+                .map(|_| reader.read_u16())
+                .collect::<Result<Vec<_>, _>>()?;
+            Some((table, ordt_type == 1))
+        } else {
+            None
+        };
+
+        Ok(Self {
+            length: field(0x04)? as usize,
+            idxt_offset: field(0x14)? as usize,
+            count: field(0x18)?,
+            code_page,
+            ordt,
+        })
+    }
+}
+
+/// Reads the TAGX table at `offset`, returning the number of control bytes
+/// in front of the tag values of every entry, and the tags.
+fn read_tagx(data: &[u8], offset: usize) -> Result<(usize, Vec<Tag>), MobiError> {
+    let tagx = data.get(offset..).ok_or(MobiError::Truncated)?;
+    check_magic("TAGX", tagx.get(..4).ok_or(MobiError::Truncated)?)?;
+    let mut reader = &tagx[4..];
+    let length = reader.read_u32()? as usize;
+    let control_bytes = reader.read_u32()? as usize;
+    let table = tagx.get(12..length).ok_or(MobiError::Truncated)?;
+    let tags = table
+        .chunks_exact(4)
+        .map(|tag| Tag {
+            tag: tag[0],
+            values_per_entry: tag[1],
+            mask: tag[2],
+            end_of_control_byte: tag[3] == 1,
+        })
+        .collect();
+    Ok((control_bytes, tags))
+}
+
+/// Reads the tag values of an index entry. The bits of each tag's mask in the
+/// control bytes say how many values it has; if they are all set, a
+/// variable-width number of bytes follows instead.
+fn read_tag_values(
+    data: &[u8],
+    control_bytes: usize,
+    tags: &[Tag],
+) -> Result<HashMap<u8, Vec<u32>>, MobiError> {
+    let control = data.get(..control_bytes).ok_or(MobiError::Truncated)?;
+    let mut reader = &data[control_bytes..];
+
+    let mut counts = vec![];
+    let mut control_byte = 0;
+    for tag in tags {
+        if tag.end_of_control_byte {
+            control_byte += 1;
+            continue;
+        }
+        if tag.mask == 0 {
+            continue;
+        }
+        let byte = *control.get(control_byte).ok_or(MobiError::Truncated)?;
+        let value = byte & tag.mask;
+        if value == 0 {
+            continue;
+        }
+        if value == tag.mask && tag.mask.count_ones() > 1 {
+            counts.push((tag, None, read_varint(&mut reader)?));
+        } else {
+            counts.push((tag, Some(value >> tag.mask.trailing_zeros()), 0));
+        }
+    }
+
+    let mut values = HashMap::new();
+    for (tag, count, byte_count) in counts {
+        let mut tag_values = vec![];
+        match count {
+            Some(count) => {
+                for _ in 0..count as usize * tag.values_per_entry as usize {
+                    tag_values.push(read_varint(&mut reader)?);
+                }
+            }
+            None => {
+                let end = reader
+                    .len()
+                    .checked_sub(byte_count as usize)
+                    .ok_or(MobiError::Truncated)?;
+                while reader.len() > end {
+                    tag_values.push(read_varint(&mut reader)?);
+                }
+            }
+        }
+        values.insert(tag.tag, tag_values);
+    }
+    Ok(values)
+}
+
+/// Reads a variable-width number, 7 bits per byte with the high bit set on
+/// the last byte.
+fn read_varint(reader: &mut &[u8]) -> Result<u32, MobiError> {
+    let mut value = 0u32;
+    loop {
+        let byte = reader.read_u8()?;
+        if value >> 25 != 0 {
+            return Err(MobiError::InvalidData(
+                "Index value is too large".to_owned(),
+            ));
+        }
+        value = value << 7 | (byte & 0x7F) as u32;
+        if byte & 0x80 != 0 {
+            return Ok(value);
+        }
+    }
+}
+
+/// Maps a label through an ORDT table into UTF-8. Characters past the end of
+/// the table are code points themselves.
+fn map_ordt(label: &[u8], ordt: &[u16], single_byte: bool) -> Vec<u8> {
+    let units: Vec<u16> = if single_byte {
+        label.iter().map(|&b| b as u16).collect()
+    } else {
+        label
+            .chunks_exact(2)
+            .map(|unit| u16::from_be_bytes([unit[0], unit[1]]))
+            .collect()
+    };
+    let units: Vec<u16> = units
+        .into_iter()
+        .map(|unit| ordt.get(unit as usize).copied().unwrap_or(unit))
+        .collect();
+    String::from_utf16_lossy(&units).into_bytes()
+}
+
+/// Applies an inflection rule to a headword. Bytes 0x01 to 0x04 switch
+/// between inserting at the start or the end of the word and deleting from
+/// the start or the end, 0x0A to 0x13 move the cursor back from the end, and
+/// everything else is a byte to insert or delete. Returns `None` for rules
+/// that don't fit the word.
+fn apply_inflection_rule(word: &[u8], rule: &[u8]) -> Option<Vec<u8>> {
+    let mut word = word.to_vec();
+    let mut mode = None;
+    let mut position = word.len();
+    for &byte in rule {
+        match byte {
+            0x01 | 0x04 => {
+                if !matches!(mode, Some(0x01 | 0x04)) {
+                    position = 0;
+                }
+                mode = Some(byte);
+            }
+            0x02 | 0x03 => {
+                if !matches!(mode, Some(0x02 | 0x03)) {
+                    position = word.len();
+                }
+                mode = Some(byte);
+            }
+            0x0A..=0x13 => {
+                if !matches!(mode, Some(0x02 | 0x03)) {
+                    mode = Some(0x02);
+                    position = word.len();
+                }
+                position = position.checked_sub((byte - 0x0A) as usize)?;
+            }
+            0x14.. => match mode? {
+                0x01 => {
+                    word.insert(position, byte);
+                    position += 1;
+                }
+                // Inserting at the end keeps the cursor where it is, so the
+                // rule stores those bytes in reverse.
+                0x02 => word.insert(position, byte),
+                0x03 => {
+                    position = position.checked_sub(1)?;
+                    if word.remove(position) != byte {
+                        return None;
+                    }
+                }
+                _ => {
+                    if *word.get(position)? != byte {
+                        return None;
+                    }
+                    word.remove(position);
+                }
+            },
+            _ => return None,
+        }
+    }
+    Some(word)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mobi_writer::MobiWriter;
+    use byyte::be::ByteWriter;
+    use std::io::Cursor;
+
+    fn varint(value: u32) -> u8 {
+        value as u8 | 0x80
+    }
+
+    /// An INDX record with a 0xC0 byte header followed by `body`, and an IDXT
+    /// for `entries`.
+    fn indx(count: u32, body: &[u8], entries: &[Vec<u8>]) -> Vec<u8> {
+        let mut data = b"INDX".to_vec();
+        data.resize(0xC0, 0);
+        data[4..8].copy_from_slice(&0xC0u32.to_be_bytes());
+        data[0x18..0x1C].copy_from_slice(&count.to_be_bytes());
+        data[0x1C..0x20].copy_from_slice(&65001u32.to_be_bytes());
+        data.extend_from_slice(body);
+        let mut offsets = vec![];
+        for entry in entries {
+            offsets.push(data.len() as u16);
+            data.extend_from_slice(entry);
+        }
+        if !entries.is_empty() {
+            let idxt = data.len() as u32;
+            data[0x14..0x18].copy_from_slice(&idxt.to_be_bytes());
+            data.extend_from_slice(b"IDXT");
+            for offset in offsets {
+                data.write_u16(offset).unwrap();
+            }
+        }
+        data
+    }
+
+    fn tagx(tags: &[[u8; 4]]) -> Vec<u8> {
+        let mut data = b"TAGX".to_vec();
+        data.write_u32(12 + 4 * tags.len() as u32).unwrap();
+        data.write_u32(1).unwrap();
+        data.extend(tags.iter().flatten());
+        data
+    }
+
+    #[test]
+    fn test_dictionary_lookup() {
+        let html = "<p><b>run</b> to move fast</p><p><b>walk</b> to move slowly</p>";
+        let run = html.find("<p><b>run").unwrap() as u32;
+        let walk = html.find("<p><b>walk").unwrap() as u32;
+
+        let orthographic = [
+            indx(
+                1,
+                &tagx(&[
+                    [1, 1, 0x01, 0],
+                    [2, 1, 0x02, 0],
+                    [0x2A, 1, 0x04, 0],
+                    [0, 0, 0, 1],
+                ]),
+                &[],
+            ),
+            indx(
+                2,
+                &[],
+                &[
+                    [
+                        b"\x03run".as_slice(),
+                        // No length, the definition ends where the next starts.
+                        &[0x05, varint(run), varint(0)],
+                    ]
+                    .concat(),
+                    [
+                        b"\x04walk".as_slice(),
+                        &[0x03, varint(walk), varint(html.len() as u32 - walk)],
+                    ]
+                    .concat(),
+                ],
+            ),
+        ];
+        // A group with two inflections, "runs" and "ran".
+        let inflections = [
+            indx(
+                1,
+                &tagx(&[[0x05, 1, 0x03, 0], [0x1A, 1, 0x0C, 0], [0, 0, 0, 1]]),
+                &[],
+            ),
+            indx(
+                3,
+                &[],
+                &[
+                    vec![0, 0x0A, varint(0), varint(0), varint(1), varint(2)],
+                    b"\x02\x02s".to_vec(),
+                    b"\x06\x03nu\x02na".to_vec(),
+                ],
+            ),
+        ];
+
+        let mut writer = MobiWriter::new("Dictionary".to_owned());
+        writer.set_content(html.to_owned());
+        let mut book = writer.into_book();
+        book.resources = orthographic.into_iter().chain(inflections).collect();
+        let mut mobi = MOBI::from_bytes(&mut Cursor::new(book.to_bytes().unwrap())).unwrap();
+        let first_index = 1 + mobi.palmdoc_header.record_count as u32;
+        mobi.header.orthographic_index = first_index;
+        mobi.header.inflection_index = first_index + 2;
+
+        let dictionary = Dictionary::from_mobi(&mobi).unwrap();
+        assert_eq!(dictionary.entries().len(), 2);
+        assert_eq!(dictionary.entries()[0].inflections, ["runs", "ran"]);
+
+        let entries = dictionary.lookup("Ran");
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].headword, "run");
+        let definition = dictionary.definition(entries[0]).unwrap();
+        assert!(definition.contains("to move fast"), "{definition}");
+        assert!(!definition.contains("walk"));
+        assert!(
+            dictionary
+                .definition(dictionary.lookup("walk")[0])
+                .unwrap()
+                .ends_with("slowly</p>")
+        );
+        let mut outside = entries[0].clone();
+        outside.definition = 0..html.len() + 1;
+        assert_eq!(dictionary.definition(&outside), None);
+        assert!(dictionary.lookup("jump").is_empty());
+    }
+
+    #[test]
+    fn test_apply_inflection_rule() {
+        assert_eq!(
+            apply_inflection_rule(b"run", b"\x02s"),
+            Some(b"runs".to_vec())
+        );
+        assert_eq!(
+            apply_inflection_rule(b"run", b"\x03nu\x02na"),
+            Some(b"ran".to_vec())
+        );
+        assert_eq!(
+            apply_inflection_rule(b"do", b"\x01un"),
+            Some(b"undo".to_vec())
+        );
+        assert_eq!(apply_inflection_rule(b"run", b"\x03x"), None);
+    }
+}
@@ -0,0 +1,57 @@
+use crate::mobi_header::MOBIHeader;
+use crate::palmdoc_header::PalmDOCHeader;
+use std::fmt::{Display, Formatter};
+
+/// The DRM scheme of a book, from the encryption type in the PalmDOC header.
+/// This crate only detects DRM, encrypted text is never decrypted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DrmInfo {
+    None,
+    /// Encryption type 1, used by early versions of Mobipocket Reader.
+    OldMobipocket,
+    /// Encryption type 2, with the vouchers for the reading devices stored
+    /// in record 0.
+    Mobipocket {
+        /// Offset of the vouchers in record 0.
+        offset: u32,
+        count: u32,
+        size: u32,
+        flags: u32,
+    },
+    Unknown(u16),
+}
+
+impl DrmInfo {
+    pub fn from_headers(palmdoc_header: &PalmDOCHeader, header: &MOBIHeader) -> Self {
+        match palmdoc_header.encryption_type {
+            0 => DrmInfo::None,
+            1 => DrmInfo::OldMobipocket,
+            2 => DrmInfo::Mobipocket {
+                offset: header.drm_offset,
+                count: header.drm_count,
+                size: header.drm_size,
+                flags: header.drm_flags,
+            },
+            encryption_type => DrmInfo::Unknown(encryption_type),
+        }
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        *self != DrmInfo::None
+    }
+}
+
+impl Display for DrmInfo {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DrmInfo::None => write!(f, "no DRM"),
+            DrmInfo::OldMobipocket => write!(f, "old Mobipocket DRM"),
+            DrmInfo::Mobipocket { count, .. } => {
+                write!(f, "Mobipocket DRM with {count} vouchers")
+            }
+            DrmInfo::Unknown(encryption_type) => {
+                write!(f, "unknown encryption type {encryption_type}")
+            }
+        }
+    }
+}
@@ -0,0 +1,219 @@
+use crate::error::MobiError;
+use crate::exth_header::{AUTHOR, COVER_OFFSET, EXTHHeader, UPDATED_TITLE};
+use crate::limits::ParseLimits;
+use crate::mobi::MOBI;
+use crate::mobi_header::{EXTH_FLAG, NULL_INDEX};
+use byyte::be::ByteWriter;
+use palm_database::{PDB, RecordSource};
+use std::io::{Read, Seek, Write};
+
+// Fields patched in record 0, which starts with the 16 byte PalmDOC header.
+const FULL_NAME_OFFSET: usize = 16 + 68;
+const FULL_NAME_LENGTH: usize = 16 + 72;
+const EXTH_FLAGS: usize = 16 + 112;
+
+/// Changes the metadata of an existing book without touching its text. Only
+/// record 0 is rebuilt, the raw MOBI header is kept as is apart from the
+/// full name and EXTH fields, and all other records are written back
+/// byte-for-byte unless the cover is replaced.
+pub struct MobiEditor {
+    mobi: MOBI,
+    /// Stored in the encoding of the book, characters it can't represent
+    /// are written as `?`.
+    pub full_name: String,
+    /// Written after the MOBI header if it has any records.
+    pub exth: EXTHHeader,
+}
+
+impl MobiEditor {
+    pub fn new(mobi: MOBI) -> Result<Self, MobiError> {
+        let full_name = mobi.full_name()?;
+        let exth = mobi.exth.clone().unwrap_or_default();
+        Ok(Self {
+            mobi,
+            full_name,
+            exth,
+        })
+    }
+
+    /// Reads a book with the default [`ParseLimits`].
+    pub fn from_bytes<R: Read + Seek>(reader: &mut R) -> Result<Self, MobiError> {
+        Self::new(MOBI::from_bytes(reader)?)
+    }
+
+    pub fn from_bytes_with_limits<R: Read + Seek>(
+        reader: &mut R,
+        limits: &ParseLimits,
+    ) -> Result<Self, MobiError> {
+        Self::new(MOBI::from_bytes_with_limits(reader, limits)?)
+    }
+
+    pub fn mobi(&self) -> &MOBI {
+        &self.mobi
+    }
+
+    /// The PDB name, at most 31 bytes.
+    pub fn name(&self) -> &str {
+        &self.mobi.pdb.header.name
+    }
+
+    pub fn set_name(&mut self, name: impl Into<String>) {
+        self.mobi.pdb.header.name = name.into();
+    }
+
+    /// Sets the full name, and the EXTH updated title if the book has one
+    /// since readers prefer it.
+    pub fn set_title(&mut self, title: impl Into<String>) {
+        self.full_name = title.into();
+        if self.exth.get(UPDATED_TITLE).is_some() {
+            let title = self.mobi.encoding().encode_lossy(&self.full_name);
+            self.exth.set(UPDATED_TITLE, title);
+        }
+    }
+
+    pub fn set_author(&mut self, author: &str) {
+        self.exth
+            .set(AUTHOR, self.mobi.encoding().encode_lossy(author));
+    }
+
+    /// The record of the cover image, from the EXTH cover offset.
+    pub fn cover_index(&self) -> Option<u16> {
+        let first_image = self.mobi.header.first_image_index;
+        let offset = self.exth.get_u32(COVER_OFFSET)?;
+        if first_image == NULL_INDEX || offset == NULL_INDEX {
+            return None;
+        }
+        u16::try_from(first_image.checked_add(offset)?)
+            .ok()
+            .filter(|&index| index < self.mobi.pdb.header.number_of_records)
+    }
+
+    /// Replaces the cover image record and returns the old image. The
+    /// thumbnail, if the book has a separate one, is left alone.
+    pub fn replace_cover(&mut self, image: Vec<u8>) -> Result<Vec<u8>, MobiError> {
+        let index = self
+            .cover_index()
+            .ok_or_else(|| MobiError::InvalidData("The book has no cover image".to_owned()))?;
+        Ok(self.mobi.pdb.replace_record(index, image)?)
+    }
+
+    fn record0(&self) -> Result<Vec<u8>, MobiError> {
+        rebuild_record0(
+            &self.mobi.pdb.record_data(0)?,
+            self.mobi.header.header_length,
+            self.mobi.header.exth_flags,
+            &self.exth,
+            &self.mobi.encoding().encode_lossy(&self.full_name),
+        )
+    }
+
+    pub fn to_pdb(&self) -> Result<PDB, MobiError> {
+        let mut pdb = self.mobi.pdb.clone();
+        pdb.replace_record(0, self.record0()?)?;
+        Ok(pdb)
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, MobiError> {
+        Ok(self.to_pdb()?.to_bytes()?)
+    }
+
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<(), MobiError> {
+        Ok(self.to_pdb()?.write_to(writer)?)
+    }
+}
+
+/// Builds record 0 from the PalmDOC and MOBI headers at the start of
+/// `original`, followed by the EXTH header and the full name. It is padded to
+/// at least its original size so that other tools editing in place keep their
+/// room.
+pub(crate) fn rebuild_record0(
+    original: &[u8],
+    header_length: u32,
+    exth_flags: u32,
+    exth: &EXTHHeader,
+    full_name: &[u8],
+) -> Result<Vec<u8>, MobiError> {
+    let header_end = 16 + header_length as usize;
+    if header_end < EXTH_FLAGS + 4 {
+        return Err(MobiError::InvalidData(format!(
+            "MOBI header length {header_length} is too short to edit"
+        )));
+    }
+    let mut record0 = original
+        .get(..header_end)
+        .ok_or(MobiError::Truncated)?
+        .to_vec();
+
+    let mut exth_flags = exth_flags & !EXTH_FLAG;
+    if !exth.records.is_empty() {
+        exth_flags |= EXTH_FLAG;
+        record0.extend_from_slice(&exth.to_bytes()?);
+    }
+    let full_name_offset = record0.len() as u32;
+    record0.extend_from_slice(full_name);
+    let len = original.len().max(record0.len() + 2);
+    record0.resize(len.next_multiple_of(4), 0);
+
+    let mut patch = |offset: usize, value: u32| -> Result<(), MobiError> {
+        (&mut record0[offset..offset + 4]).write_u32(value)?;
+        Ok(())
+    };
+    patch(FULL_NAME_OFFSET, full_name_offset)?;
+    patch(FULL_NAME_LENGTH, full_name.len() as u32)?;
+    patch(EXTH_FLAGS, exth_flags)?;
+    Ok(record0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mobi_writer::MobiWriter;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_edit_metadata() {
+        let html = "<html><body><p>Unchanged</p></body></html>";
+        let mut writer = MobiWriter::new("Old".to_owned());
+        writer.set_content(html.to_owned());
+        writer.add_image(b"old cover".to_vec());
+        let original = MOBI::from_bytes(&mut Cursor::new(writer.to_bytes().unwrap())).unwrap();
+
+        let mut editor = MobiEditor::new(original.clone()).unwrap();
+        assert_eq!(editor.full_name, "Old");
+        assert_eq!(editor.cover_index(), None);
+        editor.set_name("New");
+        editor.set_title("A much longer title than the one before");
+        editor.set_author("Author");
+        editor.exth.set_u32(COVER_OFFSET, 0);
+        assert_eq!(
+            editor.replace_cover(b"new cover".to_vec()).unwrap(),
+            b"old cover"
+        );
+
+        let bytes = editor.to_bytes().unwrap();
+        let edited = MOBI::from_bytes(&mut Cursor::new(bytes)).unwrap();
+        assert_eq!(edited.pdb.header.name, "New");
+        assert_eq!(
+            edited.full_name().unwrap(),
+            "A much longer title than the one before"
+        );
+        let exth = edited.exth.clone().unwrap();
+        assert_eq!(exth.get_string(AUTHOR).as_deref(), Some("Author"));
+        assert_eq!(edited.text().unwrap(), html);
+        assert_eq!(edited.pdb.read_record(2).unwrap(), b"new cover");
+        for i in [1, 3, 4, 5] {
+            assert_eq!(edited.pdb.read_record(i), original.pdb.read_record(i));
+        }
+        let record0 = edited.pdb.read_record(0).unwrap();
+        assert_eq!(record0.len() % 4, 0);
+        assert_eq!(&record0[..84], &original.pdb.read_record(0).unwrap()[..84]);
+
+        // Editing again keeps the EXTH records and room for the longer title.
+        let mut editor = MobiEditor::new(edited).unwrap();
+        editor.set_title("Short");
+        let edited = MOBI::from_bytes(&mut Cursor::new(editor.to_bytes().unwrap())).unwrap();
+        assert_eq!(edited.full_name().unwrap(), "Short");
+        assert_eq!(edited.exth.unwrap().records, exth.records);
+        assert_eq!(edited.pdb.read_record(0).unwrap().len(), record0.len());
+    }
+}
@@ -0,0 +1,58 @@
+use crate::drm::DrmInfo;
+use palm_database::PDBError;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum MobiError {
+    #[error("I/O error: {0}")]
+    Io(#[source] std::io::Error),
+    #[error(transparent)]
+    Pdb(#[from] PDBError),
+    #[error("Expected {expected:?} but found {found:?}")]
+    BadMagic {
+        expected: &'static str,
+        found: String,
+    },
+    /// A record ended in the middle of a header or trailing entry.
+    #[error("Unexpected end of record data")]
+    Truncated,
+    #[error("Unsupported compression: {0}")]
+    UnsupportedCompression(u16),
+    /// The text can't be read without removing the DRM, which this crate
+    /// doesn't do.
+    #[error("The book text is encrypted ({0})")]
+    Encrypted(DrmInfo),
+    #[error("The {what} ({value}) exceeds the limit of {limit}")]
+    LimitExceeded {
+        what: &'static str,
+        value: u64,
+        limit: u64,
+    },
+    #[error("Link to unknown anchor #{0}")]
+    UnknownAnchor(String),
+    #[error("Anchor #{0} is beyond the filepos range")]
+    FileposOverflow(String),
+    #[error("Invalid data: {0}")]
+    InvalidData(String),
+}
+
+impl From<std::io::Error> for MobiError {
+    fn from(err: std::io::Error) -> Self {
+        match err.kind() {
+            std::io::ErrorKind::UnexpectedEof => MobiError::Truncated,
+            _ => MobiError::Io(err),
+        }
+    }
+}
+
+/// Checks a four character identifier such as `MOBI` or `EXTH`.
+pub(crate) fn check_magic(expected: &'static str, found: &[u8]) -> Result<(), MobiError> {
+    if found == expected.as_bytes() {
+        Ok(())
+    } else {
+        Err(MobiError::BadMagic {
+            expected,
+            found: String::from_utf8_lossy(found).into_owned(),
+        })
+    }
+}
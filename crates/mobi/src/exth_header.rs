@@ -1,31 +1,221 @@
-use byyte::be::ByteReader;
+use crate::error::{MobiError, check_magic};
+use crate::limits::ParseLimits;
+use byyte::be::{ByteReader, ByteWriter};
+use std::io::{Read, Write};
 
-#[derive(Debug, Clone)]
+pub const AUTHOR: u32 = 100;
+pub const PUBLISHER: u32 = 101;
+pub const DESCRIPTION: u32 = 103;
+pub const ISBN: u32 = 104;
+pub const SUBJECT: u32 = 105;
+pub const PUBLISHING_DATE: u32 = 106;
+pub const ASIN: u32 = 113;
+/// Offset of the cover image from `first_image_index`.
+pub const COVER_OFFSET: u32 = 201;
+/// Offset of the thumbnail image from `first_image_index`.
+pub const THUMB_OFFSET: u32 = 202;
+/// The document type, e.g. `EBOK` or `PDOC`.
+pub const CDE_TYPE: u32 = 501;
+pub const UPDATED_TITLE: u32 = 503;
+pub const LANGUAGE: u32 = 524;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EXTHRecord {
+    pub type_: u32,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Default)]
 pub struct EXTHHeader {
     pub header_length: u32,
     pub record_count: u32,
+    pub records: Vec<EXTHRecord>,
 }
 
 impl EXTHHeader {
-    pub fn from_bytes<R: std::io::Read + std::io::Seek>(reader: &mut R) -> anyhow::Result<Self> {
-        let identifier = reader.read_cstr(4)?;
-        assert_eq!(identifier, "EXTH");
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The data of the first record of the given type.
+    pub fn get(&self, type_: u32) -> Option<&[u8]> {
+        self.records
+            .iter()
+            .find(|record| record.type_ == type_)
+            .map(|record| record.data.as_slice())
+    }
+
+    pub fn get_string(&self, type_: u32) -> Option<String> {
+        self.get(type_)
+            .map(|data| String::from_utf8_lossy(data).into_owned())
+    }
+
+    pub fn get_u32(&self, type_: u32) -> Option<u32> {
+        self.get(type_)?.try_into().ok().map(u32::from_be_bytes)
+    }
+
+    /// Replaces all records of the given type with a single one, kept at the
+    /// position of the first.
+    pub fn set(&mut self, type_: u32, data: impl Into<Vec<u8>>) {
+        let data = data.into();
+        match self.records.iter().position(|record| record.type_ == type_) {
+            Some(index) => {
+                self.records[index].data = data;
+                let mut first = true;
+                self.records
+                    .retain(|record| record.type_ != type_ || std::mem::take(&mut first));
+            }
+            None => self.records.push(EXTHRecord { type_, data }),
+        }
+        self.record_count = self.records.len() as u32;
+    }
+
+    pub fn set_u32(&mut self, type_: u32, value: u32) {
+        self.set(type_, value.to_be_bytes());
+    }
+
+    pub fn remove(&mut self, type_: u32) {
+        self.records.retain(|record| record.type_ != type_);
+        self.record_count = self.records.len() as u32;
+    }
+
+    /// Reads the header with the default [`ParseLimits`].
+    pub fn from_bytes<R: std::io::Read + std::io::Seek>(reader: &mut R) -> Result<Self, MobiError> {
+        Self::from_bytes_with_limits(reader, &ParseLimits::default())
+    }
+
+    pub fn from_bytes_with_limits<R: std::io::Read + std::io::Seek>(
+        reader: &mut R,
+        limits: &ParseLimits,
+    ) -> Result<Self, MobiError> {
+        let mut identifier = [0u8; 4];
+        reader.read_exact(&mut identifier)?;
+        check_magic("EXTH", &identifier)?;
 
         let header_length = reader.read_u32()?;
         let record_count = reader.read_u32()?;
+        if record_count > limits.max_exth_records {
+            return Err(MobiError::LimitExceeded {
+                what: "EXTH record count",
+                value: record_count as u64,
+                limit: limits.max_exth_records as u64,
+            });
+        }
 
+        let mut records = vec![];
         for _ in 0..record_count {
             let type_ = reader.read_u32()?;
             let len = reader.read_u32()?;
-            let mut data = vec![0u8; len as usize - 8];
-            reader.read_exact(&mut data)?;
-
-            eprintln!("{type_}: {:?}", String::from_utf8(data));
+            let data_len = len.checked_sub(8).ok_or_else(|| {
+                MobiError::InvalidData(format!(
+                    "EXTH record length {len} is shorter than its header"
+                ))
+            })?;
+            // Read through `take` so a bogus length can't allocate more than
+            // the reader holds.
+            let mut data = vec![];
+            Read::take(&mut *reader, data_len as u64).read_to_end(&mut data)?;
+            if data.len() != data_len as usize {
+                return Err(MobiError::Truncated);
+            }
+            records.push(EXTHRecord { type_, data });
         }
 
         Ok(EXTHHeader {
             header_length,
             record_count,
+            records,
         })
     }
+
+    /// Writes the header and its records, padded to a multiple of 4 bytes.
+    /// The padding is not counted in the header length.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, MobiError> {
+        let mut data = vec![];
+        data.write_all(b"EXTH")?;
+        let length: usize = self
+            .records
+            .iter()
+            .map(|record| 8 + record.data.len())
+            .sum();
+        data.write_u32(12 + length as u32)?;
+        data.write_u32(self.records.len() as u32)?;
+        for record in &self.records {
+            data.write_u32(record.type_)?;
+            data.write_u32(8 + record.data.len() as u32)?;
+            data.write_all(&record.data)?;
+        }
+        data.resize(data.len().next_multiple_of(4), 0);
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_exth_malformed() {
+        let header = |records: &[u8]| {
+            let mut data = b"EXTH".to_vec();
+            data.extend_from_slice(&(12 + records.len() as u32).to_be_bytes());
+            data.extend_from_slice(&1u32.to_be_bytes());
+            data.extend_from_slice(records);
+            data
+        };
+
+        // Record length shorter than the record header itself.
+        let data = header(&[0, 0, 0, 100, 0, 0, 0, 4]);
+        assert!(matches!(
+            EXTHHeader::from_bytes(&mut Cursor::new(data)),
+            Err(MobiError::InvalidData(_))
+        ));
+
+        // Record running past the end of the data.
+        let data = header(&[0, 0, 0, 100, 0, 0, 0, 20, b'x']);
+        assert!(matches!(
+            EXTHHeader::from_bytes(&mut Cursor::new(data)),
+            Err(MobiError::Truncated)
+        ));
+
+        let data = header(&[0, 0, 0, 100, 0, 0, 0, 9, b'x']);
+        let exth = EXTHHeader::from_bytes(&mut Cursor::new(&data)).unwrap();
+        assert_eq!(exth.get(AUTHOR), Some(&b"x"[..]));
+        let limits = ParseLimits {
+            max_exth_records: 0,
+            ..ParseLimits::default()
+        };
+        assert!(matches!(
+            EXTHHeader::from_bytes_with_limits(&mut Cursor::new(&data), &limits),
+            Err(MobiError::LimitExceeded { .. })
+        ));
+
+        assert!(matches!(
+            EXTHHeader::from_bytes(&mut Cursor::new(b"EXTX")),
+            Err(MobiError::BadMagic { .. })
+        ));
+    }
+
+    #[test]
+    fn test_exth_round_trip() {
+        let mut exth = EXTHHeader::new();
+        exth.set(AUTHOR, "First");
+        exth.set(PUBLISHER, "Publisher");
+        exth.records.push(EXTHRecord {
+            type_: AUTHOR,
+            data: b"Second".to_vec(),
+        });
+        exth.set_u32(COVER_OFFSET, 1);
+        exth.set(AUTHOR, "Author");
+
+        let data = exth.to_bytes().unwrap();
+        assert_eq!(data.len() % 4, 0);
+        let read = EXTHHeader::from_bytes(&mut Cursor::new(data)).unwrap();
+        assert_eq!(read.records, exth.records);
+        assert_eq!(read.record_count, 3);
+        assert_eq!(read.get_string(AUTHOR).as_deref(), Some("Author"));
+        assert_eq!(read.records[0].type_, AUTHOR);
+        assert_eq!(read.get_u32(COVER_OFFSET), Some(1));
+    }
 }
@@ -1,6 +1,16 @@
 pub mod mobi;
+pub mod apnx;
+pub mod book;
 pub mod compression;
+pub mod dictionary;
+pub mod drm;
+pub mod editor;
+pub mod error;
 pub mod exth_header;
+pub mod limits;
 pub mod mobi_header;
+pub mod palmdoc;
 pub mod palmdoc_header;
-pub mod mobi_writer;
\ No newline at end of file
+pub mod mobi_writer;
+pub mod links;
+pub mod validate;
\ No newline at end of file
@@ -0,0 +1,31 @@
+/// Bounds on what the MOBI parsers will accept, on top of the limits for the
+/// underlying database.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseLimits {
+    pub pdb: palm_database::ParseLimits,
+    /// Maximum size of the decompressed book text.
+    pub max_decompressed_size: usize,
+    /// Maximum number of records in the EXTH header.
+    pub max_exth_records: u32,
+}
+
+impl Default for ParseLimits {
+    fn default() -> Self {
+        Self {
+            pdb: palm_database::ParseLimits::default(),
+            max_decompressed_size: 64 * 1024 * 1024,
+            max_exth_records: 4096,
+        }
+    }
+}
+
+impl ParseLimits {
+    /// No limits beyond what the formats themselves allow.
+    pub fn unlimited() -> Self {
+        Self {
+            pdb: palm_database::ParseLimits::unlimited(),
+            max_decompressed_size: usize::MAX,
+            max_exth_records: u32::MAX,
+        }
+    }
+}
@@ -0,0 +1,385 @@
+use crate::error::MobiError;
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// Width of the zero padded offset written into `filepos` attributes.
+pub const FILEPOS_WIDTH: usize = 10;
+
+/// An attribute found inside a tag, with byte ranges relative to the tag start.
+#[derive(Debug, Clone)]
+pub struct Attribute {
+    pub name: String,
+    pub value: String,
+    /// Range covering the whole `name="value"` text.
+    pub range: Range<usize>,
+}
+
+/// Finds the end of the tag starting at `start` (which must point at `<`),
+/// returning the index just past the closing `>`.
+pub fn tag_end(html: &[u8], start: usize) -> Option<usize> {
+    let mut quote = None;
+    for (i, &b) in html.iter().enumerate().skip(start + 1) {
+        match (quote, b) {
+            (Some(q), _) if b == q => quote = None,
+            (Some(_), _) => {}
+            (None, b'"' | b'\'') => quote = Some(b),
+            (None, b'>') => return Some(i + 1),
+            (None, _) => {}
+        }
+    }
+    None
+}
+
+/// Whether a `<name` tag starts at `start`, ignoring case. `/name` matches
+/// closing tags.
+pub(crate) fn is_tag(html: &[u8], start: usize, name: &[u8]) -> bool {
+    html.get(start) == Some(&b'<')
+        && html
+            .get(start + 1..start + 1 + name.len())
+            .is_some_and(|tag_name| tag_name.eq_ignore_ascii_case(name))
+        && html
+            .get(start + 1 + name.len())
+            .is_none_or(|&b| b.is_ascii_whitespace() || b == b'>' || b == b'/')
+}
+
+/// Finds the next `<name` tag at or after `from`, ignoring case.
+pub(crate) fn find_tag(html: &[u8], from: usize, name: &[u8]) -> Option<usize> {
+    (from..html.len()).find(|&i| is_tag(html, i, name))
+}
+
+/// Parses the attributes of a single tag such as `<a href="#x" id=y>`.
+pub fn parse_attributes(tag: &[u8]) -> Vec<Attribute> {
+    let mut attributes = vec![];
+    let is_name = |b: u8| !b.is_ascii_whitespace() && !matches!(b, b'=' | b'>' | b'/' | b'<');
+
+    // Skip `<` and the tag name.
+    let mut i = 1;
+    while i < tag.len() && is_name(tag[i]) {
+        i += 1;
+    }
+
+    while i < tag.len() {
+        while i < tag.len() && !is_name(tag[i]) {
+            i += 1;
+        }
+        let start = i;
+        while i < tag.len() && is_name(tag[i]) {
+            i += 1;
+        }
+        if start == i {
+            break;
+        }
+        let name = String::from_utf8_lossy(&tag[start..i]).to_ascii_lowercase();
+
+        let mut j = i;
+        while j < tag.len() && tag[j].is_ascii_whitespace() {
+            j += 1;
+        }
+        if j >= tag.len() || tag[j] != b'=' {
+            attributes.push(Attribute {
+                name,
+                value: String::new(),
+                range: start..i,
+            });
+            continue;
+        }
+        j += 1;
+        while j < tag.len() && tag[j].is_ascii_whitespace() {
+            j += 1;
+        }
+
+        let (value, end) = match tag.get(j) {
+            Some(&q @ (b'"' | b'\'')) => {
+                let close = tag[j + 1..]
+                    .iter()
+                    .position(|&b| b == q)
+                    .map(|p| j + 1 + p)
+                    .unwrap_or(tag.len());
+                (&tag[j + 1..close], (close + 1).min(tag.len()))
+            }
+            _ => {
+                let mut k = j;
+                while k < tag.len()
+                    && !tag[k].is_ascii_whitespace()
+                    && tag[k] != b'>'
+                    && !tag[k..].starts_with(b"/>")
+                {
+                    k += 1;
+                }
+                (&tag[j..k], k)
+            }
+        };
+
+        attributes.push(Attribute {
+            name,
+            value: String::from_utf8_lossy(value).into_owned(),
+            range: start..end,
+        });
+        i = end;
+    }
+
+    attributes
+}
+
+/// Replaces symbolic links (`href="#name"`) with fixed width `filepos` offsets
+/// pointing at the tag carrying the matching `id="name"`.
+///
+/// The returned text is what should be stored in the text records, since the
+/// offsets are only valid for this exact byte layout.
+pub fn resolve_anchors(html: &str) -> Result<String, MobiError> {
+    let output = resolve_anchor_bytes(html.as_bytes())?;
+    String::from_utf8(output).map_err(|err| MobiError::InvalidData(err.to_string()))
+}
+
+/// Like [`resolve_anchors`], for text that is already encoded, e.g. as
+/// Windows-1252. The offsets are byte offsets into the returned text.
+pub fn resolve_anchor_bytes(bytes: &[u8]) -> Result<Vec<u8>, MobiError> {
+    let mut output = Vec::with_capacity(bytes.len());
+    let mut anchors = HashMap::new();
+    let mut links = vec![];
+
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'<' {
+            output.push(bytes[i]);
+            i += 1;
+            continue;
+        }
+
+        let end = tag_end(bytes, i).unwrap_or(bytes.len());
+        let tag = &bytes[i..end];
+        let tag_offset = output.len();
+        let mut last = 0;
+
+        for attribute in parse_attributes(tag) {
+            match attribute.name.as_str() {
+                "id" | "name" if !attribute.value.is_empty() => {
+                    anchors.entry(attribute.value).or_insert(tag_offset);
+                }
+                "href" if attribute.value.starts_with('#') => {
+                    output.extend_from_slice(&tag[last..attribute.range.start]);
+                    output.extend_from_slice(b"filepos=");
+                    links.push((output.len(), attribute.value[1..].to_owned()));
+                    output.extend_from_slice(&[b'0'; FILEPOS_WIDTH]);
+                    last = attribute.range.end;
+                }
+                _ => {}
+            }
+        }
+
+        output.extend_from_slice(&tag[last..]);
+        i = end;
+    }
+
+    for (position, name) in links {
+        let offset = anchors
+            .get(&name)
+            .ok_or_else(|| MobiError::UnknownAnchor(name.clone()))?;
+        let digits = format!("{:0width$}", offset, width = FILEPOS_WIDTH);
+        if digits.len() > FILEPOS_WIDTH {
+            return Err(MobiError::FileposOverflow(name));
+        }
+        output[position..position + FILEPOS_WIDTH].copy_from_slice(digits.as_bytes());
+    }
+
+    Ok(output)
+}
+
+pub(crate) fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Reverses [`escape_html`].
+pub(crate) fn unescape_html(text: &str) -> String {
+    text.replace("&quot;", "\"")
+        .replace("&gt;", ">")
+        .replace("&lt;", "<")
+        .replace("&amp;", "&")
+}
+
+/// A `<reference>` entry from the `<guide>` section of a book.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GuideReference {
+    pub type_: String,
+    pub title: String,
+    pub filepos: usize,
+}
+
+fn filepos_value(attribute: &Attribute) -> Option<usize> {
+    if attribute.name == "filepos" {
+        attribute.value.trim().parse().ok()
+    } else {
+        None
+    }
+}
+
+/// Lists the guide references (`<reference type="toc" filepos=...>` etc.) in the text.
+pub fn guide_references(html: &[u8]) -> Vec<GuideReference> {
+    let mut references = vec![];
+    let mut i = 0;
+    while let Some(start) = html[i..].iter().position(|&b| b == b'<').map(|p| i + p) {
+        let end = tag_end(html, start).unwrap_or(html.len());
+        let tag = &html[start..end];
+        if tag.len() > 10 && tag[1..10].eq_ignore_ascii_case(b"reference") {
+            let attributes = parse_attributes(tag);
+            let value = |name: &str| {
+                attributes
+                    .iter()
+                    .find(|a| a.name == name)
+                    .map(|a| a.value.clone())
+                    .unwrap_or_default()
+            };
+            if let Some(filepos) = attributes.iter().find_map(filepos_value) {
+                references.push(GuideReference {
+                    type_: value("type"),
+                    title: value("title"),
+                    filepos,
+                });
+            }
+        }
+        i = end;
+    }
+    references
+}
+
+/// The inverse of [`resolve_anchors`]: inserts `<a id="fileposN"></a>` at every
+/// offset referenced by a `filepos` attribute and rewrites those attributes to
+/// `href="#fileposN"`, so the extracted HTML can be navigated in a browser.
+///
+/// Guide references are rewritten the same way. Targets that are a tag with
+/// an `id`, like the ones [`resolve_anchors`] links to, keep that `id` instead
+/// of getting an anchor, so resolving and anchorizing gives back the original.
+pub fn anchorize(html: &[u8]) -> Vec<u8> {
+    let mut targets = vec![];
+    let mut i = 0;
+    while let Some(start) = html[i..].iter().position(|&b| b == b'<').map(|p| i + p) {
+        let end = tag_end(html, start).unwrap_or(html.len());
+        targets.extend(parse_attributes(&html[start..end]).iter().filter_map(filepos_value));
+        i = end;
+    }
+    targets.sort_unstable();
+    targets.dedup();
+    let anchors: Vec<(usize, Option<String>)> = targets
+        .iter()
+        .map(|&target| (target, target_id(html, target)))
+        .collect();
+    let anchor_id = |target: usize| {
+        let i = anchors.binary_search_by_key(&target, |anchor| anchor.0).ok()?;
+        anchors[i].1.clone()
+    };
+
+    let mut output = Vec::with_capacity(html.len() + targets.len() * 32);
+    let mut pending = anchors.iter().peekable();
+    let mut emit_anchors = |output: &mut Vec<u8>, position: usize| {
+        while let Some((target, existing)) = pending.next_if(|anchor| anchor.0 <= position) {
+            if existing.is_none() {
+                output.extend_from_slice(format!("<a id=\"filepos{target}\"></a>").as_bytes());
+            }
+        }
+    };
+
+    let mut i = 0;
+    while i < html.len() {
+        let b = html[i];
+        if b & 0xC0 != 0x80 {
+            emit_anchors(&mut output, i);
+        }
+        if b != b'<' {
+            output.push(b);
+            i += 1;
+            continue;
+        }
+
+        let end = tag_end(html, i).unwrap_or(html.len());
+        let tag = &html[i..end];
+        let mut last = 0;
+        for attribute in parse_attributes(tag) {
+            if let Some(filepos) = filepos_value(&attribute) {
+                output.extend_from_slice(&tag[last..attribute.range.start]);
+                let id = anchor_id(filepos).unwrap_or_else(|| format!("filepos{filepos}"));
+                output.extend_from_slice(format!("href=\"#{id}\"").as_bytes());
+                last = attribute.range.end;
+            }
+        }
+        output.extend_from_slice(&tag[last..]);
+        i = end;
+    }
+    emit_anchors(&mut output, usize::MAX);
+
+    output
+}
+
+/// The `id` of the tag starting at `start`, if it has one.
+fn target_id(html: &[u8], start: usize) -> Option<String> {
+    if html.get(start) != Some(&b'<') {
+        return None;
+    }
+    let end = tag_end(html, start)?;
+    parse_attributes(&html[start..end])
+        .into_iter()
+        .find(|attribute| attribute.name == "id")
+        .map(|attribute| attribute.value)
+        .filter(|id| !id.is_empty() && !id.contains('"'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_anchors() {
+        let html = r##"<p><a href="#ch2">Chapter 2</a></p><h1 id="ch2">Chapter 2</h1>"##;
+        let resolved = resolve_anchors(html).expect("Failed to resolve anchors");
+
+        let offset = resolved.find("<h1").unwrap();
+        assert_eq!(
+            resolved,
+            format!(r#"<p><a filepos={:010}>Chapter 2</a></p><h1 id="ch2">Chapter 2</h1>"#, offset)
+        );
+    }
+
+    #[test]
+    fn test_resolve_unknown_anchor() {
+        assert!(matches!(
+            resolve_anchors(r##"<a href="#missing">x</a>"##),
+            Err(MobiError::UnknownAnchor(name)) if name == "missing"
+        ));
+    }
+
+    #[test]
+    fn test_anchorize_round_trip() {
+        let html = r##"<guide><reference type="toc" title="Contents" href="#toc"/></guide><p id="toc"><a href="#ch1">One</a></p><p id="ch1">Text</p>"##;
+        let resolved = resolve_anchors(html).unwrap();
+        let toc = resolved.find("<p id=\"toc\"").unwrap();
+
+        assert_eq!(
+            guide_references(resolved.as_bytes()),
+            vec![GuideReference {
+                type_: "toc".to_owned(),
+                title: "Contents".to_owned(),
+                filepos: toc,
+            }]
+        );
+
+        // Targets with an id link to it again.
+        let anchored = String::from_utf8(anchorize(resolved.as_bytes())).unwrap();
+        assert_eq!(anchored, html);
+
+        // Other targets get an anchor.
+        let target = "<a filepos=0000000000>One</a>".len();
+        let resolved = format!("<a filepos={target:010}>One</a><p>Text</p>");
+        assert_eq!(
+            String::from_utf8(anchorize(resolved.as_bytes())).unwrap(),
+            format!(r##"<a href="#filepos{target}">One</a><a id="filepos{target}"></a><p>Text</p>"##)
+        );
+    }
+
+    #[test]
+    fn test_external_links_untouched() {
+        let html = r#"<a href="https://example.com">x</a>"#;
+        assert_eq!(resolve_anchors(html).unwrap(), html);
+    }
+}
@@ -1,184 +1,109 @@
-use std::io::{Read, Write};
-use crate::exth_header::EXTHHeader;
+use std::io::Cursor;
+use crate::compression::decompress_text;
+use crate::drm::DrmInfo;
+use crate::editor::rebuild_record0;
+use crate::error::MobiError;
+use crate::limits::ParseLimits;
+use crate::links::{anchorize, guide_references, GuideReference};
 pub use crate::mobi_header::MOBIHeader;
 pub use crate::palmdoc_header::PalmDOCHeader;
-use anyhow::{anyhow, Result};
-use byyte::be::ByteWriter;
-use palm_database::{PDBHeader, PDB};
-use rand::random;
-use palm_database::timestamp::to_palm_timestamp;
-use crate::mobi_header::NULL_INDEX;
+use palm_database::{RecordSource, TextEncoding, PDB};
+use crate::exth_header::EXTHHeader;
+use crate::mobi_header::EXTH_FLAG;
 
+/// A MOBI book on top of a record source, which is an in-memory [`PDB`] by
+/// default but can also be e.g. a [`palm_database::lazy::LazyPdb`].
 #[derive(Debug, Clone)]
-pub struct MOBI {
+pub struct MOBI<P = PDB> {
     pub palmdoc_header: PalmDOCHeader,
     pub header: MOBIHeader,
-    pub pdb: PDB,
-    pub content: String,
+    /// Present if the EXTH flag is set in the MOBI header.
+    pub exth: Option<EXTHHeader>,
+    pub pdb: P,
 
     pub multibyte: bool,
     pub trailers: u8,
+    /// Applied when reading the book text.
+    pub limits: ParseLimits,
 }
 
 impl MOBI {
-    pub fn new(name: &str) -> Self {
-        let slf = Self {
-            palmdoc_header: PalmDOCHeader {
-                compression: 2,
-                text_length: 0,
-                record_count: 0,
-                record_size: 4096,
-                encryption_type: 0,
-            },
-            header: MOBIHeader {
-                identifier: "MOBI".to_string(),
-                header_length: 232,
-                mobi_type: 2,
-                text_encoding: 65001,
-                unique_id: random(),
-                file_version: 6,
-                orthographic_index: NULL_INDEX,
-                inflection_index: NULL_INDEX,
-                index_names: NULL_INDEX,
-                index_keys: NULL_INDEX,
-                extra_index0: NULL_INDEX,
-                extra_index1: NULL_INDEX,
-                extra_index2: NULL_INDEX,
-                extra_index3: NULL_INDEX,
-                extra_index4: NULL_INDEX,
-                extra_index5: NULL_INDEX,
-                first_non_book_index: 0,
-                full_name_offset: 0,
-                full_name_length: 0,
-                locale: 9,
-                input_language: 0,
-                output_language: 0,
-                min_version: 6,
-                first_image_index: 0,
-                huffman_record_offset: 0,
-                huffman_record_count: 0,
-                huffman_table_offset: 0,
-                huffman_table_length: 0,
-                exth_flags: 0,
-                extra_record_data_flags: 0,
-                first_content_record_number: 1,
-                last_content_record_number: 0,
-                fcis_record_number: 0,
-                flis_record_number: 0,
-            },
-            pdb: PDB::new(PDBHeader{
-                name: name.to_string(),
-                attributes: 0,
-                version: 0,
-                creation_time: chrono::Local::now().naive_local(),
-                modification_time: chrono::Local::now().naive_local(),
-                last_backup_date: chrono::Local::now().naive_local(),
-                modification_number: 0,
-                app_info_id: 0,
-                sort_info_id: 0,
-                type_: "BOOK".to_string(),
-                creator: "MOBI".to_string(),
-                unique_id_seed: 0,
-                next_record_list_id: 0,
-                number_of_records: 0,
-            }),
-            content: "".to_string(),
-            multibyte: false,
-            trailers: 0,
-        };
-
-        slf
+    /// Reads a book with the default [`ParseLimits`].
+    pub fn from_bytes<R: std::io::Read + std::io::Seek>(reader: &mut R) -> Result<Self, MobiError> {
+        Self::from_bytes_with_limits(reader, &ParseLimits::default())
     }
-    pub fn set_content(&mut self, content: &str) {
-        let record_count = (content.len() / self.palmdoc_header.record_size as usize) + if (content.len() % self.palmdoc_header.record_size as usize) > 0 { 1 } else { 0 };
-        self.palmdoc_header.text_length = content.len() as u32;
-        self.header.last_content_record_number += record_count as u16;
-        self.palmdoc_header.record_count = record_count as u16;
-        self.content = content.to_string();
 
-        self.header.fcis_record_number = self.header.last_content_record_number as u32 + 1;
-        self.header.flis_record_number = self.header.last_content_record_number as u32 + 2;
+    pub fn from_bytes_with_limits<R: std::io::Read + std::io::Seek>(
+        reader: &mut R,
+        limits: &ParseLimits,
+    ) -> Result<Self, MobiError> {
+        Self::from_source_with_limits(PDB::from_bytes_with_limits(reader, &limits.pdb)?, limits)
     }
 
-    fn serialize_content(&mut self) {
-        let content = self.content.clone();
-        // self.palmdoc_header.text_length = content.len() as u32;
-        // let record_count = (content.len() / self.palmdoc_header.record_size as usize) + if (content.len() % self.palmdoc_header.record_size as usize) > 0 { 1 } else { 0 };
-        // self.header.last_content_record_number += record_count as u16;
-        // self.palmdoc_header.record_count = record_count as u16;
+    /// Writes the book back as read: record 0 is updated from the headers,
+    /// keeping reserved and unknown header bytes, and every other record is
+    /// kept byte-for-byte. New books are written with [`MobiBook`].
+    ///
+    /// [`MobiBook`]: crate::book::MobiBook
+    pub fn to_bytes(&self) -> Result<Vec<u8>, MobiError> {
+        let mut pdb = self.pdb.clone();
+        pdb.replace_record(0, self.updated_record0()?)?;
+        Ok(pdb.to_bytes()?)
+    }
 
-        let mut remaining_bytes = content.as_bytes();
+    /// Writes the headers over the original record 0. The EXTH header and
+    /// full name are only rebuilt if the EXTH records were changed.
+    fn updated_record0(&self) -> Result<Vec<u8>, MobiError> {
+        let mut record0 = self.pdb.record_data(0)?.into_owned();
+        let mut cursor = Cursor::new(record0.get(16..).ok_or(MobiError::Truncated)?);
+        let original_header = MOBIHeader::from_bytes(&mut cursor)?;
+        let original_exth = if original_header.exth_flags & EXTH_FLAG != 0 {
+            cursor.set_position(original_header.header_length as u64);
+            Some(EXTHHeader::from_bytes_with_limits(&mut cursor, &self.limits)?)
+        } else {
+            None
+        };
 
-        for _ in 0..self.palmdoc_header.record_count {
-            let bytes = &remaining_bytes.take(self.palmdoc_header.record_size as u64).into_inner();
-            let mut data = palmdoc_compression::compress(bytes);
+        let palmdoc_header = self.palmdoc_header.to_bytes()?;
+        // Bytes 2..4 and 14..16 are reserved.
+        record0[..2].copy_from_slice(&palmdoc_header[..2]);
+        record0[4..14].copy_from_slice(&palmdoc_header[4..14]);
+        let header_end = 16 + original_header.raw.len();
+        record0.splice(16..header_end, self.header.to_bytes()?);
 
-            self.pdb.add_record(data);
-            if bytes.len() == self.palmdoc_header.record_size as usize {
-                remaining_bytes = &remaining_bytes[self.palmdoc_header.record_size as usize..];
-            }
+        let exth_changed = self.exth.as_ref().map(|exth| &exth.records)
+            != original_exth.as_ref().map(|exth| &exth.records);
+        if exth_changed {
+            record0 = rebuild_record0(
+                &record0,
+                self.header.header_length,
+                self.header.exth_flags,
+                &self.exth.clone().unwrap_or_default(),
+                &self.raw_full_name()?,
+            )?;
         }
+        Ok(record0)
+    }
+}
 
+impl<P: RecordSource> MOBI<P> {
+    /// Reads the MOBI headers from record 0 of `pdb`. Other records are only
+    /// read when requested.
+    pub fn from_source(pdb: P) -> Result<Self, MobiError> {
+        Self::from_source_with_limits(pdb, &ParseLimits::default())
     }
-    pub fn add_flis(&mut self) -> anyhow::Result<()> {
-        let mut data = vec![];
-        data.write("FLIS".as_bytes())?;
-        data.write_u32(8)?;
-        data.write_u16(65)?;
-        data.write_u16(0)?;
-        data.write_u32(0)?;
-        data.write_u32(0xFFFFFFFF)?;
-        data.write_u16(1)?;
-        data.write_u16(3)?;
-        data.write_u32(3)?;
-        data.write_u32(1)?;
-        data.write_u32(0xFFFFFFFF)?;
-
-        let id = self.pdb.add_record(data);
-        // self.header.flis_record_number = id as u32;
-
-        Ok(())
-    }
-    pub fn add_fcis(&mut self) -> anyhow::Result<()> {
-        let mut data = vec![];
-        data.write("FCIS".as_bytes())?;
-        data.write_u32(20)?;
-        data.write_u32(16)?;
-        data.write_u32(1)?;
-        data.write_u32(0)?;
-        data.write_u32(self.palmdoc_header.text_length)?;
-        data.write_u32(0)?;
-        data.write_u32(32)?;
-        data.write_u32(4)?;
-        data.write_u16(1)?;
-        data.write_u16(1)?;
-        data.write_u32(0)?;
-
-        let id = self.pdb.add_record(data);
-        // self.header.fcis_record_number = id as u32;
-
-        Ok(())
-    }
-    pub fn add_eof(&mut self) -> anyhow::Result<()> {
-        let mut data = vec![];
-        data.write_u8(233)?;
-        data.write_u8(142)?;
-        data.write_u8(13)?;
-        data.write_u8(10)?;
-        self.pdb.add_record(data);
-
-        Ok(())
-    }
-    pub fn from_bytes<R: std::io::Read + std::io::Seek>(reader: &mut R) -> Result<Self> {
-        let pdb = PDB::from_bytes(reader)?;
-
-        let first_record = pdb
-            .read_record(0)
-            .ok_or(anyhow!("Failed to read mobi header"))?;
-        let mut first_record_cursor = std::io::Cursor::new(first_record);
+
+    pub fn from_source_with_limits(pdb: P, limits: &ParseLimits) -> Result<Self, MobiError> {
+        let first_record = pdb.record_data(0)?;
+        let mut first_record_cursor = std::io::Cursor::new(first_record.as_ref());
         let palmdoc_header = PalmDOCHeader::from_bytes(&mut first_record_cursor)?;
         let header = MOBIHeader::from_bytes(&mut first_record_cursor)?;
-        // let exth = EXTHHeader::from_bytes(&mut first_record_cursor)?;
+        let exth = if header.exth_flags & EXTH_FLAG != 0 {
+            first_record_cursor.set_position(16 + header.header_length as u64);
+            Some(EXTHHeader::from_bytes_with_limits(&mut first_record_cursor, limits)?)
+        } else {
+            None
+        };
 
         let mut multibyte = false;
         let mut trailers = 0;
@@ -189,63 +114,225 @@ impl MOBI {
 
             while flags > 1 {
                 trailers += 1;
-                flags = flags & (flags - 2);
+                flags &= flags - 2;
             }
         }
 
         Ok(MOBI {
             palmdoc_header,
             header,
-            content: String::new(),
+            exth,
             pdb,
             multibyte,
             trailers,
+            limits: *limits,
         })
     }
 
-    pub fn read_record(&self, index: u16) -> Result<Vec<u8>> {
-        let mut bytes = self
-            .pdb
-            .read_record(index)
-            .ok_or(anyhow!("Failed to read text record"))?;
+    pub fn drm(&self) -> DrmInfo {
+        DrmInfo::from_headers(&self.palmdoc_header, &self.header)
+    }
+
+    /// The encoding of the text, full name and EXTH strings. Unknown code
+    /// pages are read as UTF-8.
+    pub fn encoding(&self) -> TextEncoding {
+        TextEncoding::from_code_page(self.header.text_encoding).unwrap_or_default()
+    }
+
+    /// The full title stored in record 0, which unlike the PDB name is not
+    /// limited to 31 bytes.
+    pub fn full_name(&self) -> Result<String, MobiError> {
+        Ok(self.encoding().decode(&self.raw_full_name()?))
+    }
+
+    pub(crate) fn raw_full_name(&self) -> Result<Vec<u8>, MobiError> {
+        let record0 = self.pdb.record_data(0)?;
+        let start = self.header.full_name_offset as usize;
+        let name = start
+            .checked_add(self.header.full_name_length as usize)
+            .and_then(|end| record0.get(start..end))
+            .ok_or(MobiError::Truncated)?;
+        Ok(name.to_vec())
+    }
+
+    /// An EXTH string such as [`AUTHOR`](crate::exth_header::AUTHOR), decoded
+    /// with the encoding of the book.
+    pub fn exth_string(&self, type_: u32) -> Option<String> {
+        let data = self.exth.as_ref()?.get(type_)?;
+        Some(self.encoding().decode(data))
+    }
+
+    /// Reads a text record with its trailing entries stripped.
+    pub fn read_record(&self, index: u16) -> Result<Vec<u8>, MobiError> {
+        let mut bytes = self.pdb.record_data(index)?.into_owned();
 
         for _ in 0..self.trailers {
-            if bytes.len() < 4 {
-                continue;
-            }
-            let mut num = 0;
-
-            let end_bytes = &bytes[bytes.len() - 4..];
-            for v in 0..4 {
-                if end_bytes[v] & 0x80 != 0 {
-                    num = 0;
-                }
-                num = (num << 7) | (end_bytes[v] & 0x7F);
-            }
-            bytes = bytes[..bytes.len() - num as usize].to_vec();
+            let size = trailing_entry_size(&bytes);
+            let len = bytes.len().checked_sub(size).ok_or(MobiError::Truncated)?;
+            bytes.truncate(len);
         }
         if self.multibyte {
-            let num = (bytes[bytes.len() - 1] & 3) + 1;
-            bytes = bytes[..bytes.len() - num as usize].to_vec();
+            let last = *bytes.last().ok_or(MobiError::Truncated)?;
+            let len = bytes
+                .len()
+                .checked_sub((last & 3) as usize + 1)
+                .ok_or(MobiError::Truncated)?;
+            bytes.truncate(len);
         }
 
         Ok(bytes)
     }
 
-    pub fn to_bytes(&self) -> anyhow::Result<Vec<u8>> {
-        let mut output = self.clone();
-        let mut writer = Vec::new();
-        writer.extend_from_slice(&self.palmdoc_header.to_bytes()?);
-        writer.extend_from_slice(&self.header.to_bytes()?);
-        output.pdb.add_record(writer);
-        output.serialize_content();
+    /// Reads and decompresses the text records into the raw book text. Fails
+    /// with [`MobiError::Encrypted`] for books with DRM.
+    pub fn raw_text(&self) -> Result<Vec<u8>, MobiError> {
+        let drm = self.drm();
+        if drm.is_encrypted() {
+            return Err(MobiError::Encrypted(drm));
+        }
 
-        output.header.first_non_book_index = self.pdb.records.len() as u32;
+        decompress_text(
+            self.palmdoc_header.compression,
+            self.palmdoc_header.text_length,
+            (1..=self.palmdoc_header.record_count).map(|i| self.read_record(i)),
+            self.limits.max_decompressed_size,
+        )
+    }
 
-        output.add_flis()?;
-        output.add_fcis()?;
-        output.add_eof()?;
+    pub fn text(&self) -> Result<String, MobiError> {
+        Ok(self.encoding().decode(&self.raw_text()?))
+    }
 
-        Ok(output.pdb.to_bytes()?)
+    /// The book text with `filepos` links turned into `href="#..."` anchors.
+    pub fn html(&self) -> Result<String, MobiError> {
+        Ok(self.encoding().decode(&anchorize(&self.raw_text()?)))
+    }
+
+    pub fn guide(&self) -> Result<Vec<GuideReference>, MobiError> {
+        Ok(guide_references(&self.raw_text()?))
+    }
+}
+
+/// Decodes the size of the last trailing entry, stored as a variable width
+/// integer at the very end of the record.
+fn trailing_entry_size(bytes: &[u8]) -> usize {
+    let mut size = 0;
+    for &b in &bytes[bytes.len().saturating_sub(4)..] {
+        if b & 0x80 != 0 {
+            size = 0;
+        }
+        size = (size << 7) | (b & 0x7F) as usize;
+    }
+    size
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::editor::MobiEditor;
+    use crate::exth_header::{AUTHOR, COVER_OFFSET};
+    use crate::mobi_writer::{MobiWriter, UniqueId};
+    use palm_database::lazy::LazyPdb;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_read_lazily() {
+        let html = "<html><body><p>Lazy</p></body></html>";
+        let mut writer = MobiWriter::new("Test".to_owned());
+        writer.set_content(html.to_owned());
+        let bytes = writer.to_bytes().unwrap();
+
+        let pdb = LazyPdb::new(Cursor::new(bytes)).expect("Failed to read PDB");
+        let mobi = MOBI::from_source(pdb).expect("Failed to read MOBI");
+        assert_eq!(mobi.palmdoc_header.record_count, 1);
+        assert_eq!(mobi.text().unwrap(), html);
+    }
+
+    #[test]
+    fn test_bad_magic() {
+        let mut writer = MobiWriter::new("Test".to_owned());
+        writer.set_content("<p>Hi</p>".to_owned());
+        let mut pdb = PDB::from_bytes(&mut Cursor::new(writer.to_bytes().unwrap())).unwrap();
+
+        let mut record0 = pdb.read_record(0).unwrap();
+        record0[16..20].copy_from_slice(b"MOBY");
+        pdb.replace_record(0, record0).unwrap();
+        assert!(matches!(
+            MOBI::from_source(pdb),
+            Err(MobiError::BadMagic { expected: "MOBI", found }) if found == "MOBY"
+        ));
+    }
+
+    #[test]
+    fn test_drm_detection() {
+        let mut writer = MobiWriter::new("Test".to_owned());
+        writer.set_content("<p>Secret</p>".to_owned());
+        let mut pdb = PDB::from_bytes(&mut Cursor::new(writer.to_bytes().unwrap())).unwrap();
+        let mobi = MOBI::from_source(pdb.clone()).unwrap();
+        assert_eq!(mobi.drm(), DrmInfo::None);
+
+        let mut record0 = pdb.read_record(0).unwrap();
+        record0[12..14].copy_from_slice(&2u16.to_be_bytes());
+        record0[16 + 152..16 + 160].copy_from_slice(&[0, 0, 1, 0, 0, 0, 0, 1]);
+        pdb.replace_record(0, record0).unwrap();
+        let mobi = MOBI::from_source(pdb).unwrap();
+        let drm = DrmInfo::Mobipocket {
+            offset: 256,
+            count: 1,
+            size: 0,
+            flags: 0,
+        };
+        assert_eq!(mobi.drm(), drm);
+        assert!(matches!(mobi.text(), Err(MobiError::Encrypted(info)) if info == drm));
+    }
+
+    #[test]
+    fn test_text_limit() {
+        let mut writer = MobiWriter::new("Test".to_owned());
+        writer.set_content("<p>".to_owned() + &"x".repeat(10000) + "</p>");
+        let bytes = writer.to_bytes().unwrap();
+
+        let limits = ParseLimits {
+            max_decompressed_size: 5000,
+            ..ParseLimits::default()
+        };
+        let mobi = MOBI::from_bytes_with_limits(&mut Cursor::new(bytes), &limits).unwrap();
+        assert!(matches!(
+            mobi.raw_text(),
+            Err(MobiError::LimitExceeded { limit: 5000, .. })
+        ));
+    }
+
+    #[test]
+    fn test_lossless_round_trip() {
+        let mut writer = MobiWriter::new("Golden".to_owned());
+        writer.set_content("<p>".to_owned() + &"Round trip. ".repeat(1000) + "</p>");
+        writer.add_image(vec![0xFF, 0xD8, 0xFF, 0xE0]);
+        writer.set_unique_id(UniqueId::Fixed(1));
+        let bytes = writer.to_bytes().unwrap();
+        let mut editor = MobiEditor::from_bytes(&mut Cursor::new(bytes)).unwrap();
+        editor.set_author("Author");
+        editor.exth.set_u32(COVER_OFFSET, 0);
+        let mut pdb = editor.to_pdb().unwrap();
+        // A header field this crate doesn't model.
+        let mut record0 = pdb.read_record(0).unwrap();
+        record0[16 + 120] = 0xAB;
+        pdb.replace_record(0, record0).unwrap();
+        let golden = pdb.to_bytes().unwrap();
+
+        let mut mobi = MOBI::from_bytes(&mut Cursor::new(&golden)).unwrap();
+        assert_eq!(mobi.to_bytes().unwrap(), golden);
+
+        mobi.header.locale = 9;
+        mobi.exth.as_mut().unwrap().set(AUTHOR, "Other");
+        let edited = MOBI::from_bytes(&mut Cursor::new(mobi.to_bytes().unwrap())).unwrap();
+        assert_eq!(edited.header.locale, 9);
+        let exth = edited.exth.as_ref().unwrap();
+        assert_eq!(exth.get_string(AUTHOR).as_deref(), Some("Other"));
+        assert_eq!(edited.full_name().unwrap(), "Golden");
+        assert_eq!(edited.pdb.read_record(0).unwrap()[16 + 120], 0xAB);
+        for i in 1..mobi.pdb.header.number_of_records {
+            assert_eq!(edited.pdb.read_record(i), mobi.pdb.read_record(i));
+        }
     }
 }
@@ -1,7 +1,10 @@
-use std::io::Write;
+use crate::error::{MobiError, check_magic};
 use byyte::be::{ByteReader, ByteWriter};
+use std::io::{Read, Write};
 
 pub const NULL_INDEX: u32 = 0xFFFFFFFF;
+/// Set in `exth_flags` when an EXTH header follows the MOBI header.
+pub const EXTH_FLAG: u32 = 0x40;
 
 #[derive(Debug, Clone)]
 pub struct MOBIHeader {
@@ -34,16 +37,34 @@ pub struct MOBIHeader {
     pub huffman_table_offset: u32,
     pub huffman_table_length: u32,
     pub exth_flags: u32,
+    /// Offset of the DRM vouchers in record 0, or [`NULL_INDEX`].
+    pub drm_offset: u32,
+    pub drm_count: u32,
+    pub drm_size: u32,
+    pub drm_flags: u32,
     pub extra_record_data_flags: u32,
+    pub indx_record_offset: u32,
     pub first_content_record_number: u16,
     pub last_content_record_number: u16,
     pub fcis_record_number: u32,
     pub flis_record_number: u32,
+    /// The header as read, so that [`MOBIHeader::to_bytes`] keeps the fields
+    /// this crate doesn't know about. Empty for new headers.
+    pub raw: Vec<u8>,
 }
 
+/// Byte ranges of the fields [`MOBIHeader::to_bytes`] writes from the struct
+/// rather than from `raw`.
+const KNOWN_FIELDS: [std::ops::Range<usize>; 6] =
+    [0..116, 152..168, 176..180, 184..188, 192..196, 224..232];
+
 impl MOBIHeader {
-    pub fn from_bytes<R: std::io::Read + std::io::Seek>(reader: &mut R) -> anyhow::Result<Self> {
-        let identifier = reader.read_cstr(4)?;
+    pub fn from_bytes<R: std::io::Read + std::io::Seek>(reader: &mut R) -> Result<Self, MobiError> {
+        let start = reader.stream_position()?;
+        let mut identifier = [0u8; 4];
+        reader.read_exact(&mut identifier)?;
+        check_magic("MOBI", &identifier)?;
+        let identifier = "MOBI".to_owned();
         let header_length = reader.read_u32()?;
         let mobi_type = reader.read_u32()?;
         let text_encoding = reader.read_u32()?;
@@ -89,16 +110,16 @@ impl MOBIHeader {
         reader.seek_relative(4)?;
         reader.seek_relative(8)?;
         reader.seek_relative(4)?;
-        let first_compilation_data_section_count = reader.read_u32()?;
-        let number_of_first_compilation_data_sections = reader.read_u32()?;
+        reader.seek_relative(8)?; // First compilation data section count and number of sections
         reader.seek_relative(4)?;
         let extra_record_data_flags = reader.read_u32()?;
-
         let indx_record_offset = reader.read_u32()?;
 
-        let offset = reader.stream_position()?;
-
-        eprintln!("Read MOBI header. New offset: {:#}", offset);
+        let end = reader.stream_position()?;
+        reader.seek(std::io::SeekFrom::Start(start))?;
+        let mut raw = vec![];
+        Read::take(&mut *reader, header_length as u64).read_to_end(&mut raw)?;
+        reader.seek(std::io::SeekFrom::Start(end))?;
 
         Ok(MOBIHeader {
             identifier,
@@ -130,18 +151,24 @@ impl MOBIHeader {
             huffman_table_offset,
             huffman_table_length,
             exth_flags,
+            drm_offset,
+            drm_count,
+            drm_size,
+            drm_flags,
             first_content_record_number,
             last_content_record_number,
             fcis_record_number,
             flis_record_number,
 
             extra_record_data_flags,
+            indx_record_offset,
+            raw,
         })
     }
 
-    pub fn to_bytes(&self) -> anyhow::Result<Vec<u8>> {
+    pub fn to_bytes(&self) -> Result<Vec<u8>, MobiError> {
         let mut data = Vec::new();
-        data.write("MOBI".as_bytes())?;
+        data.write_all("MOBI".as_bytes())?;
         data.write_u32(self.header_length)?;
         data.write_u32(self.mobi_type)?;
         data.write_u32(self.text_encoding)?;
@@ -171,13 +198,13 @@ impl MOBIHeader {
         data.write_u32(self.huffman_table_length)?;
         data.write_u32(self.exth_flags)?;
 
-        data.write(&[0u8; 32])?; // Unknown
+        data.write_all(&[0u8; 32])?; // Unknown
         data.write_u32(NULL_INDEX)?; // Unknown
 
-        data.write_u32(NULL_INDEX)?; // DRM Offset
-        data.write_u32(NULL_INDEX)?; // DRM Count
-        data.write_u32(0)?; // DRM Size
-        data.write_u32(0)?; // DRM Flags
+        data.write_u32(self.drm_offset)?;
+        data.write_u32(self.drm_count)?;
+        data.write_u32(self.drm_size)?;
+        data.write_u32(self.drm_flags)?;
 
         data.write_u32(0)?; // Bytes to end of header? docs say to use 0
         data.write_u32(0)?;
@@ -198,8 +225,18 @@ impl MOBIHeader {
         data.write_u32(NULL_INDEX)?;
         data.write_u32(NULL_INDEX)?;
         data.write_u32(self.extra_record_data_flags)?;
-        data.write_u32(NULL_INDEX)?;
-
-        Ok(data)
+        data.write_u32(self.indx_record_offset)?;
+
+        if self.raw.is_empty() {
+            return Ok(data);
+        }
+        let mut raw = self.raw.clone();
+        for range in KNOWN_FIELDS {
+            let end = range.end.min(raw.len());
+            if range.start < end {
+                raw[range.start..end].copy_from_slice(&data[range.start..end]);
+            }
+        }
+        Ok(raw)
     }
 }
@@ -1,14 +1,22 @@
+use crate::book::MobiBook;
+use crate::error::MobiError;
+use crate::links::resolve_anchor_bytes;
+use crate::mobi_header::{EXTH_FLAG, NULL_INDEX};
 use byyte::be::ByteWriter;
-use palm_database::{PDB, PDBHeader};
+use palm_database::{Epoch, TextEncoding};
+use palm_database::builder::PDBBuilder;
+use palm_database::writer::PDBWriter;
 use rand::random;
-use std::io::{Read, Write};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Cursor, Seek, Write};
+use std::path::PathBuf;
 
-const TEXT_RECORD_SIZE: usize = 4096;
-const NULL_INDEX: u32 = 0xFFFFFFFF;
+pub(crate) const TEXT_RECORD_SIZE: usize = 4096;
+pub(crate) const EOF_RECORD: [u8; 4] = [0xE9, 0x8E, 0x0D, 0x0A];
 
-pub fn fcis(text_length: u32) -> Result<Vec<u8>, anyhow::Error> {
+pub fn fcis(text_length: u32) -> Result<Vec<u8>, MobiError> {
     let mut data = vec![];
-    data.write("FCIS".as_bytes())?;
+    data.write_all("FCIS".as_bytes())?;
     data.write_u32(20)?;
     data.write_u32(16)?;
     data.write_u32(1)?;
@@ -17,15 +25,15 @@ pub fn fcis(text_length: u32) -> Result<Vec<u8>, anyhow::Error> {
     data.write_u32(0)?;
     data.write_u32(32)?;
     data.write_u32(8)?;
-    data.write_u32(1)?;
-    data.write_u32(1)?;
+    data.write_u16(1)?;
+    data.write_u16(1)?;
     data.write_u32(0)?;
     Ok(data)
 }
 
-pub fn flis() -> Result<Vec<u8>, anyhow::Error> {
+pub fn flis() -> Result<Vec<u8>, MobiError> {
     let mut data = vec![];
-    data.write("FLIS".as_bytes())?;
+    data.write_all("FLIS".as_bytes())?;
     data.write_u32(8)?;
     data.write_u16(65)?;
     data.write_u16(0)?;
@@ -40,182 +48,500 @@ pub fn flis() -> Result<Vec<u8>, anyhow::Error> {
 }
 
 pub fn eof() -> Vec<u8> {
-    vec![233, 142, 13, 10]
+    EOF_RECORD.to_vec()
+}
+
+/// Where the data for an image record comes from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ImageSource {
+    Bytes(Vec<u8>),
+    /// Read from disk while writing, so large books don't need every image in memory.
+    File(PathBuf),
+}
+
+/// How the MOBI unique ID of a written book is chosen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UniqueId {
+    /// A new ID on every write, or [`UniqueId::ContentHash`] if
+    /// `SOURCE_DATE_EPOCH` is set.
+    #[default]
+    Random,
+    Fixed(u32),
+    /// A hash of the name, metadata, text and records, so the same book always
+    /// gets the same ID.
+    ContentHash,
+}
+
+/// The time in `SOURCE_DATE_EPOCH`, the reproducible builds convention for
+/// pinning timestamps.
+pub fn source_date_epoch() -> Option<chrono::NaiveDateTime> {
+    let seconds = std::env::var("SOURCE_DATE_EPOCH").ok()?.trim().parse().ok()?;
+    Some(chrono::DateTime::from_timestamp(seconds, 0)?.naive_utc())
 }
 
+const FNV_OFFSET_BASIS: u32 = 0x811C9DC5;
+
+/// 32 bit FNV-1a, which unlike `DefaultHasher` gives the same result with
+/// every Rust version.
+fn fnv1a(hash: u32, bytes: &[u8]) -> u32 {
+    bytes
+        .iter()
+        .fold(hash, |hash, &byte| (hash ^ byte as u32).wrapping_mul(0x01000193))
+}
+
+/// Builds a book from HTML and images. Kept for existing callers, it is a
+/// thin wrapper around [`MobiBook`].
 pub struct MobiWriter {
-    name: String,
-    content: String,
-    images: Vec<Vec<u8>>,
-    text_record_count: usize,
+    book: MobiBook,
 }
 
 impl MobiWriter {
     pub fn new(name: String) -> Self {
         Self {
-            name,
-            content: "".to_owned(),
-            images: vec![],
-            text_record_count: 0,
+            book: MobiBook::new(name),
         }
     }
 
-    fn text_record_count(&self) -> usize {
-        (self.content.len() / TEXT_RECORD_SIZE)
-            + if (self.content.len() % TEXT_RECORD_SIZE) != 0 {
-                1
-            } else {
-                0
-            }
+    /// Writes Windows-1252 instead of UTF-8, for old devices.
+    pub fn set_encoding(&mut self, encoding: TextEncoding) {
+        self.book.encoding = encoding;
+    }
+
+    pub fn set_unique_id(&mut self, unique_id: UniqueId) {
+        self.book.metadata.unique_id = unique_id;
     }
 
+    /// Used as the creation and modification time. Without one the time from
+    /// `SOURCE_DATE_EPOCH` is used, or the timestamps are left unset.
+    pub fn set_timestamp(&mut self, timestamp: chrono::NaiveDateTime) {
+        self.book.metadata.timestamp = Some(timestamp);
+    }
+
+    /// Sets the book HTML. Links of the form `href="#name"` are resolved to
+    /// `filepos` offsets of the tag with `id="name"` when the book is written.
     pub fn set_content(&mut self, content: String) {
-        self.content = content;
-        self.text_record_count = self.text_record_count();
+        self.book.html = content;
     }
 
     pub fn add_image(&mut self, image: Vec<u8>) {
-        self.images.push(image);
-    }
-
-    fn generate_palmdoc(&self) -> Result<Vec<u8>, anyhow::Error> {
-        let mut data = vec![];
-        data.write_u16(1)?; // Palmdoc Compression
-        data.write_u16(0)?;
-        data.write_u32(self.content.len() as u32)?;
-        data.write_u16(self.text_record_count as u16)?;
-        data.write_u16(TEXT_RECORD_SIZE as u16)?;
-        data.write_u16(0)?; // No Encryption
-        data.write_u16(0)?; // Unknown
-        Ok(data)
-    }
-
-    fn generate_mobiheader(&self) -> Result<Vec<u8>, anyhow::Error> {
-        let first_non_book_index = self.text_record_count as u32 + 1;
-        let last_content_index = self.text_record_count as u32 + self.images.len() as u32;
-
-        let mut data = vec![];
-        data.write("MOBI".as_bytes())?;
-        data.write_u32(0xE8)?; // Header Length (might need to be updated)
-        data.write_u32(0x002)?;
-        data.write_u32(65001)?; // UTF-8
-        data.write_u32(random())?;
-        data.write_u32(6)?;
-        data.write_u32(NULL_INDEX)?;
-        data.write_u32(NULL_INDEX)?;
-        data.write_u32(NULL_INDEX)?;
-        data.write_u32(NULL_INDEX)?;
-        data.write(vec![0xFFu8; 24].as_slice())?;
-        data.write_u32(first_non_book_index)?;
-        data.write_u32(0x100)?;
-        data.write_u32(self.name.len() as u32)?;
-        data.write_u32(1033)?;
-        data.write_u32(0)?;
-        data.write_u32(0)?;
-        data.write_u32(6)?;
-        data.write_u32(first_non_book_index)?; // no index records, use page after text records as image
-        data.write(vec![0u8; 16].as_slice())?;
-        data.write_u32(0)?; // No EXTH for now
-        data.write(vec![0u8; 32].as_slice())?;
-        data.write_u32(NULL_INDEX)?; // Unknown
-
-        data.write_u32(NULL_INDEX)?; // No DRM
-        data.write_u32(NULL_INDEX)?;
-        data.write_u32(0)?;
-        data.write_u32(0)?;
-
-        data.write(vec![0u8; 8].as_slice())?;
-
-        data.write_u16(1)?;
-        data.write_u16(last_content_index as u16 - 1)?; // TODO: this might be wrong
-
-        data.write_u32(1)?;
-        data.write_u32(last_content_index)?; // FCIS
-        data.write_u32(1)?;
-        data.write_u32(last_content_index + 1)?; // FLIS
-        data.write_u32(1)?;
-
-        data.write_u32(0)?;
-        data.write_u32(0)?;
-        data.write_u32(NULL_INDEX)?;
-        data.write_u32(0)?;
-        data.write_u32(NULL_INDEX)?;
-        data.write_u32(NULL_INDEX)?;
-        data.write_u32(0)?; // No extra data
-        data.write_u32(NULL_INDEX)?; // No Index
-        data.write(vec![0u8; 8].as_slice())?;
-        // eprintln!("out {:02x}", data.len() + 0x10); // 0x100
-        data.write(self.name.as_bytes())?;
-
-        // data.write(vec![0u8; 1024].as_slice())?;
-
-        let extra = data.len() % 4;
-
-        data.write(vec![0u8; 4-extra].as_slice())?;
-
-        Ok(data)
-    }
-
-    fn generate_record0(&self) -> Result<Vec<u8>, anyhow::Error> {
-        let mut data = vec![];
-        data.write(self.generate_palmdoc()?.as_slice())?;
-        data.write(self.generate_mobiheader()?.as_slice())?;
-        Ok(data)
-    }
-
-    fn generate_text_records(&self) -> Result<Vec<Vec<u8>>, anyhow::Error> {
-        let content = self.content.as_bytes();
-        let mut records = vec![];
-
-        let mut start = 0;
-
-        for _ in 0..self.text_record_count() {
-            let bytes = content[start..content.len().min(start+TEXT_RECORD_SIZE)].to_vec();
-            // let data = palmdoc_compression::compress(bytes);
-            records.push(bytes.to_vec());
-            if bytes.len() == TEXT_RECORD_SIZE {
-                start += TEXT_RECORD_SIZE;
+        self.book.images.push(ImageSource::Bytes(image));
+    }
+
+    pub fn add_image_file(&mut self, path: impl Into<PathBuf>) {
+        self.book.images.push(ImageSource::File(path.into()));
+    }
+
+    pub fn book(&self) -> &MobiBook {
+        &self.book
+    }
+
+    pub fn into_book(self) -> MobiBook {
+        self.book
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, MobiError> {
+        self.book.to_bytes()
+    }
+
+    pub fn write_to<W: Write + Seek>(&self, writer: W) -> Result<W, MobiError> {
+        self.book.write_to(writer)
+    }
+}
+
+fn generate_palmdoc(book: &MobiBook, text: &[u8]) -> Result<Vec<u8>, MobiError> {
+    let mut data = vec![];
+    data.write_u16(book.compression)?;
+    data.write_u16(0)?;
+    data.write_u32(text.len() as u32)?;
+    data.write_u16(text.len().div_ceil(TEXT_RECORD_SIZE) as u16)?;
+    data.write_u16(TEXT_RECORD_SIZE as u16)?;
+    data.write_u16(0)?; // No Encryption
+    data.write_u16(0)?; // Unknown
+    Ok(data)
+}
+
+fn unique_id(book: &MobiBook, text: &[u8]) -> Result<u32, MobiError> {
+    let unique_id = match book.metadata.unique_id {
+        UniqueId::Random if source_date_epoch().is_some() => UniqueId::ContentHash,
+        unique_id => unique_id,
+    };
+    Ok(match unique_id {
+        UniqueId::Random => random(),
+        UniqueId::Fixed(unique_id) => unique_id,
+        UniqueId::ContentHash => {
+            // Every part ends with its length, so that moving bytes between
+            // parts changes the hash.
+            let end = |hash: u32, length: u64| fnv1a(hash, &length.to_be_bytes());
+            let add = |hash: u32, bytes: &[u8]| end(fnv1a(hash, bytes), bytes.len() as u64);
+            let exth = book.metadata.to_exth(book.encoding).to_bytes()?;
+            let mut hash = add(FNV_OFFSET_BASIS, book.metadata.title.as_bytes());
+            hash = add(hash, &exth);
+            hash = add(hash, text);
+            for image in &book.images {
+                match image {
+                    ImageSource::Bytes(bytes) => hash = add(hash, bytes),
+                    // Files are hashed a buffer at a time instead of read whole.
+                    ImageSource::File(path) => {
+                        let mut reader = BufReader::new(File::open(path)?);
+                        let mut length = 0;
+                        loop {
+                            let buffer = reader.fill_buf()?;
+                            if buffer.is_empty() {
+                                break;
+                            }
+                            hash = fnv1a(hash, buffer);
+                            length += buffer.len() as u64;
+                            let consumed = buffer.len();
+                            reader.consume(consumed);
+                        }
+                        hash = end(hash, length);
+                    }
+                }
             }
+            for resource in &book.resources {
+                hash = add(hash, resource);
+            }
+            hash
         }
+    })
+}
 
-        Ok(records)
-    }
-
-    pub fn to_bytes(&self) -> Result<Vec<u8>, anyhow::Error> {
-        let text_records = self.generate_text_records()?;
-
-        let pdb_header = PDBHeader {
-            name: self.name[..self.name.len().min(32)].to_string(),
-            attributes: 0,
-            version: 0,
-            creation_time: Default::default(),
-            modification_time: Default::default(),
-            last_backup_date: Default::default(),
-            modification_number: 0,
-            app_info_id: 0,
-            sort_info_id: 0,
-            type_: "BOOK".to_string(),
-            creator: "MOBI".to_string(),
-            unique_id_seed: 0,
-            next_record_list_id: 0,
-            number_of_records: 0,
-        };
+/// A record of a book being written, in file order.
+enum Record<'a> {
+    Header,
+    Text(Vec<u8>),
+    Image(&'a ImageSource),
+    Resource(&'a [u8]),
+    Flis,
+    Fcis,
+    Eof,
+}
 
-        let mut pdb = PDB::new(pdb_header);
-        pdb.add_record(self.generate_record0()?);
+/// The records of a book, laid out before record 0 is generated so that its
+/// record numbers come from the actual positions.
+struct Layout<'a> {
+    records: Vec<Record<'a>>,
+}
 
-        for text_record in text_records {
-            pdb.add_record(text_record);
+impl<'a> Layout<'a> {
+    fn new(book: &'a MobiBook, text_records: Vec<Vec<u8>>) -> Result<Self, MobiError> {
+        let mut records = vec![Record::Header];
+        records.extend(text_records.into_iter().map(Record::Text));
+        records.extend(book.images.iter().map(Record::Image));
+        records.extend(book.resources.iter().map(|resource| Record::Resource(resource)));
+        records.extend([Record::Flis, Record::Fcis, Record::Eof]);
+        if records.len() > u16::MAX as usize {
+            return Err(MobiError::LimitExceeded {
+                what: "number of records",
+                value: records.len() as u64,
+                limit: u16::MAX as u64,
+            });
         }
+        Ok(Self { records })
+    }
 
-        for image in self.images.iter() {
-            pdb.add_record(image.to_vec());
+    /// The number of the first matching record, or [`NULL_INDEX`].
+    fn first(&self, predicate: impl Fn(&Record) -> bool) -> u32 {
+        self.records
+            .iter()
+            .position(predicate)
+            .map_or(NULL_INDEX, |index| index as u32)
+    }
+
+    fn text_record_count(&self) -> usize {
+        self.records
+            .iter()
+            .filter(|record| matches!(record, Record::Text(_)))
+            .count()
+    }
+
+    fn first_non_book_index(&self) -> u32 {
+        self.text_record_count() as u32 + 1
+    }
+
+    fn first_image_index(&self) -> u32 {
+        self.first(|record| matches!(record, Record::Image(_)))
+    }
+
+    /// The last text, image or resource record.
+    fn last_content_record_number(&self) -> u16 {
+        self.records
+            .iter()
+            .rposition(|record| {
+                matches!(
+                    record,
+                    Record::Text(_) | Record::Image(_) | Record::Resource(_)
+                )
+            })
+            .unwrap_or(0) as u16
+    }
+}
+
+fn generate_mobiheader(
+    book: &MobiBook,
+    text: &[u8],
+    layout: &Layout,
+) -> Result<Vec<u8>, MobiError> {
+    let exth = book.metadata.to_exth(book.encoding);
+    let exth_flags = if exth.records.is_empty() { 0 } else { EXTH_FLAG };
+    let exth = if exth.records.is_empty() {
+        vec![]
+    } else {
+        exth.to_bytes()?
+    };
+    let name = book.encoding.encode_lossy(&book.metadata.title);
+
+    let mut data = vec![];
+    data.write_all("MOBI".as_bytes())?;
+    data.write_u32(0xE8)?; // Header Length (might need to be updated)
+    data.write_u32(0x002)?;
+    data.write_u32(book.encoding.code_page())?;
+    data.write_u32(unique_id(book, text)?)?;
+    data.write_u32(6)?;
+    data.write_u32(NULL_INDEX)?;
+    data.write_u32(NULL_INDEX)?;
+    data.write_u32(NULL_INDEX)?;
+    data.write_u32(NULL_INDEX)?;
+    data.write_all(&[0xFFu8; 24])?;
+    data.write_u32(layout.first_non_book_index())?;
+    data.write_u32(0x100 + exth.len() as u32)?;
+    data.write_u32(name.len() as u32)?;
+    data.write_u32(1033)?;
+    data.write_u32(0)?;
+    data.write_u32(0)?;
+    data.write_u32(6)?;
+    data.write_u32(layout.first_image_index())?;
+    data.write_all(&[0u8; 16])?;
+    data.write_u32(exth_flags)?;
+    data.write_all(&[0u8; 32])?;
+    data.write_u32(NULL_INDEX)?; // Unknown
+
+    data.write_u32(NULL_INDEX)?; // No DRM
+    data.write_u32(NULL_INDEX)?;
+    data.write_u32(0)?;
+    data.write_u32(0)?;
+
+    data.write_all(&[0u8; 8])?;
+
+    data.write_u16(1)?;
+    data.write_u16(layout.last_content_record_number())?;
+
+    data.write_u32(1)?;
+    data.write_u32(layout.first(|record| matches!(record, Record::Fcis)))?;
+    data.write_u32(1)?;
+    data.write_u32(layout.first(|record| matches!(record, Record::Flis)))?;
+    data.write_u32(1)?;
+
+    data.write_u32(0)?;
+    data.write_u32(0)?;
+    data.write_u32(NULL_INDEX)?;
+    data.write_u32(0)?;
+    data.write_u32(NULL_INDEX)?;
+    data.write_u32(NULL_INDEX)?;
+    data.write_u32(0)?; // No extra data
+    data.write_u32(NULL_INDEX)?; // No INDX records are written
+    data.write_all(&exth)?;
+    data.write_all(&[0u8; 8])?;
+    data.write_all(&name)?;
+
+    let extra = data.len() % 4;
+
+    data.write_all(vec![0u8; 4-extra].as_slice())?;
+
+    Ok(data)
+}
+
+fn generate_record0(book: &MobiBook, text: &[u8], layout: &Layout) -> Result<Vec<u8>, MobiError> {
+    let mut data = vec![];
+    data.write_all(generate_palmdoc(book, text)?.as_slice())?;
+    data.write_all(generate_mobiheader(book, text, layout)?.as_slice())?;
+    Ok(data)
+}
+
+fn generate_text_records(book: &MobiBook, text: &[u8]) -> Result<Vec<Vec<u8>>, MobiError> {
+    text
+        .chunks(TEXT_RECORD_SIZE)
+        .map(|chunk| match book.compression {
+            1 => Ok(chunk.to_vec()),
+            2 => Ok(palmdoc_compression::compress(chunk)),
+            compression => Err(MobiError::UnsupportedCompression(compression)),
+        })
+        .collect()
+}
+
+/// Encodes the HTML, writing characters the encoding can't represent as
+/// character references.
+fn encode_html(html: &str, encoding: TextEncoding) -> Vec<u8> {
+    if encoding == TextEncoding::Utf8 {
+        return html.as_bytes().to_vec();
+    }
+    let mut escaped = String::with_capacity(html.len());
+    for c in html.chars() {
+        if encoding.can_encode(c) {
+            escaped.push(c);
+        } else {
+            escaped.push_str(&format!("&#{};", c as u32));
         }
+    }
+    encoding.encode_lossy(&escaped)
+}
+
+/// Writes the book record by record, reading file backed images from disk as
+/// they are needed.
+pub(crate) fn write_book<W: Write + Seek>(book: &MobiBook, writer: W) -> Result<W, MobiError> {
+    let text = resolve_anchor_bytes(&encode_html(&book.html_with_toc(), book.encoding))?;
+    let layout = Layout::new(book, generate_text_records(book, &text)?)?;
+
+    // Like the full name, the PDB name is stored in the encoding of the book.
+    let mut name = book.encoding.decode(&book.encoding.encode_lossy(&book.metadata.title));
+    while book.encoding.encode_lossy(&name).len() > 31 {
+        name.pop();
+    }
+    let timestamp = book.metadata.timestamp.or_else(source_date_epoch);
+    let pdb_header = PDBBuilder::new()
+        .name(name)
+        .name_encoding(book.encoding)
+        .type_("BOOK")
+        .creator("MOBI")
+        // Like Calibre, so that a `SOURCE_DATE_EPOCH` of 0 or 1 can be written.
+        .epoch(Epoch::Unix1970)
+        .creation_time(timestamp)
+        .modification_time(timestamp)
+        .last_backup_date(None)
+        .build()?
+        .header;
+
+    let mut pdb = PDBWriter::new(writer, pdb_header, layout.records.len() as u16)?;
+    for record in &layout.records {
+        match record {
+            Record::Header => pdb.write_record(&generate_record0(book, &text, &layout)?)?,
+            Record::Text(data) => pdb.write_record(data)?,
+            Record::Image(ImageSource::Bytes(bytes)) => pdb.write_record(bytes)?,
+            Record::Image(ImageSource::File(path)) => {
+                pdb.write_record_from(&mut File::open(path)?)?
+            }
+            Record::Resource(data) => pdb.write_record(data)?,
+            Record::Flis => pdb.write_record(&flis()?)?,
+            Record::Fcis => pdb.write_record(&fcis(text.len() as u32)?)?,
+            Record::Eof => pdb.write_record(&eof())?,
+        };
+    }
+
+    Ok(pdb.finish()?)
+}
+
+pub(crate) fn to_bytes(book: &MobiBook) -> Result<Vec<u8>, MobiError> {
+    Ok(write_book(book, Cursor::new(Vec::new()))?.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mobi::MOBI;
+
+    #[test]
+    fn test_write_image_from_file() {
+        let path = std::env::temp_dir().join(format!("mobi_writer_{}.jpg", std::process::id()));
+        std::fs::write(&path, b"image data").unwrap();
+
+        let mut writer = MobiWriter::new("Test".to_owned());
+        writer.set_content("<html><body><img recindex=\"00001\"></body></html>".to_owned());
+        writer.add_image_file(&path);
+        let bytes = writer.write_to(Cursor::new(Vec::new())).unwrap().into_inner();
+
+        // Hashing a file gives the same ID as hashing its bytes.
+        let mut book = MobiBook::new("Test");
+        book.metadata.unique_id = UniqueId::ContentHash;
+        book.images = vec![ImageSource::File(path.clone())];
+        let from_file = unique_id(&book, b"text").unwrap();
+        book.images = vec![ImageSource::Bytes(b"image data".to_vec())];
+        assert_eq!(unique_id(&book, b"text").unwrap(), from_file);
+        std::fs::remove_file(&path).unwrap();
+
+        let mobi = MOBI::from_bytes(&mut Cursor::new(bytes)).expect("Failed to read MOBI");
+        let first_image = mobi.header.first_image_index as u16;
+        assert_eq!(mobi.pdb.read_record(first_image).unwrap(), b"image data");
+        assert_eq!(mobi.pdb.header.number_of_records, 6);
+    }
 
-        pdb.add_record(flis()?);
-        pdb.add_record(fcis(self.content.len() as u32)?);
-        pdb.add_record(eof());
+    #[test]
+    fn test_reproducible_output() {
+        let write = |content: &str, unique_id| {
+            let mut writer = MobiWriter::new("Test".to_owned());
+            writer.set_content(content.to_owned());
+            writer.add_image(b"image data".to_vec());
+            writer.set_unique_id(unique_id);
+            writer.set_timestamp(chrono::DateTime::UNIX_EPOCH.naive_utc());
+            writer.to_bytes().unwrap()
+        };
+        let unique_id = |bytes: Vec<u8>| {
+            MOBI::from_bytes(&mut Cursor::new(bytes)).unwrap().header.unique_id
+        };
+
+        let bytes = write("<p>Hello</p>", UniqueId::ContentHash);
+        assert_eq!(bytes, write("<p>Hello</p>", UniqueId::ContentHash));
+        let other = write("<p>Bye</p>", UniqueId::ContentHash);
+        assert_ne!(unique_id(bytes), unique_id(other));
+        assert_eq!(unique_id(write("<p>Hello</p>", UniqueId::Fixed(42))), 42);
+
+        // Metadata is part of the hash too.
+        let mut book = MobiBook::new("Test");
+        book.metadata.unique_id = UniqueId::ContentHash;
+        let before = super::unique_id(&book, b"text").unwrap();
+        book.metadata.authors = vec!["Author".to_owned()];
+        assert_ne!(super::unique_id(&book, b"text").unwrap(), before);
+    }
+
+    #[test]
+    fn test_write_cp1252() {
+        let mut book = MobiBook::new("Café “Noir”");
+        book.encoding = TextEncoding::Cp1252;
+        book.metadata.authors = vec!["Zoë".to_owned()];
+        book.html = r##"<p><a href="#fin">Fin</a> déjà 日本</p><p id="fin">€</p>"##.to_owned();
+        let bytes = book.to_bytes().unwrap();
+
+        let mobi = MOBI::from_bytes(&mut Cursor::new(bytes)).unwrap();
+        assert_eq!(mobi.header.text_encoding, 1252);
+        assert_eq!(mobi.pdb.header.name, "Café “Noir”");
+        assert_eq!(mobi.pdb.header.name_encoding, TextEncoding::Cp1252);
+        assert_eq!(mobi.full_name().unwrap(), "Café “Noir”");
+        assert_eq!(mobi.exth_string(crate::exth_header::AUTHOR).as_deref(), Some("Zoë"));
+        let text = mobi.text().unwrap();
+        assert!(text.contains("déjà &#26085;&#26412;"));
+        // Offsets count bytes of the encoded text.
+        let raw = mobi.raw_text().unwrap();
+        let target = raw.windows(12).position(|w| w == b"<p id=\"fin\">").unwrap();
+        assert!(text.contains(&format!("filepos={target:010}")));
+    }
 
-        Ok(pdb.to_bytes()?)
+    #[test]
+    fn test_record_numbers() {
+        let mut book = MobiBook::new("Test");
+        book.html = format!("<p>{}</p>", "Layout. ".repeat(1000));
+        book.compression = 1;
+        let mobi = MOBI::from_bytes(&mut Cursor::new(book.to_bytes().unwrap())).unwrap();
+        let header = &mobi.header;
+        assert_eq!(mobi.palmdoc_header.record_count, 2);
+        assert_eq!(header.first_non_book_index, 3);
+        assert_eq!(header.first_image_index, NULL_INDEX);
+        assert_eq!(header.last_content_record_number, 2);
+        assert_eq!(header.indx_record_offset, NULL_INDEX);
+
+        book.images = vec![
+            ImageSource::Bytes(b"\x89PNG first".to_vec()),
+            ImageSource::Bytes(b"\x89PNG second".to_vec()),
+        ];
+        book.resources = vec![b"resource".to_vec()];
+        let mobi = MOBI::from_bytes(&mut Cursor::new(book.to_bytes().unwrap())).unwrap();
+        let header = &mobi.header;
+        let record = |index: u32| mobi.pdb.read_record(index as u16).unwrap();
+        assert_eq!(header.first_non_book_index, 3);
+        assert_eq!(record(header.first_image_index), b"\x89PNG first");
+        assert_eq!(record(header.last_content_record_number as u32), b"resource");
+        assert!(record(header.flis_record_number).starts_with(b"FLIS"));
+        assert!(record(header.fcis_record_number).starts_with(b"FCIS"));
+        assert_eq!(header.fcis_record_number, 7);
+        assert_eq!(record(8), eof());
+        assert_eq!(mobi.pdb.header.number_of_records, 9);
     }
 }
@@ -0,0 +1,320 @@
+use crate::compression::decompress_text;
+use crate::error::{MobiError, check_magic};
+use crate::limits::ParseLimits;
+use crate::links::escape_html;
+use crate::book::{MobiBook, TocEntry};
+use crate::mobi_writer::TEXT_RECORD_SIZE;
+use crate::palmdoc_header::PalmDOCHeader;
+use byyte::be::{ByteReader, ByteWriter};
+use palm_database::builder::PDBBuilder;
+use palm_database::{PDB, RecordSource, TextEncoding};
+use std::io::{Cursor, Read, Seek, Write};
+
+const BOOKMARK_NAME_SIZE: usize = 16;
+const BOOKMARK_RECORD_SIZE: usize = BOOKMARK_NAME_SIZE + 4;
+
+/// A named position in the text of a PalmDOC book.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bookmark {
+    pub name: String,
+    /// Offset into the uncompressed text.
+    pub offset: u32,
+}
+
+impl Bookmark {
+    pub fn from_bytes(data: &[u8]) -> Result<Self, MobiError> {
+        if data.len() != BOOKMARK_RECORD_SIZE {
+            return Err(MobiError::InvalidData(format!(
+                "Bookmark records must be {BOOKMARK_RECORD_SIZE} bytes"
+            )));
+        }
+        let name = &data[..BOOKMARK_NAME_SIZE];
+        let name = &name[..name.iter().position(|&b| b == 0).unwrap_or(name.len())];
+        let offset = (&data[BOOKMARK_NAME_SIZE..]).read_u32()?;
+
+        Ok(Bookmark {
+            name: String::from_utf8_lossy(name).into_owned(),
+            offset,
+        })
+    }
+
+    /// Names are cut to 15 bytes so that they stay NUL terminated.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, MobiError> {
+        let mut name = self.name.clone();
+        while name.len() >= BOOKMARK_NAME_SIZE {
+            name.pop();
+        }
+
+        let mut data = name.into_bytes();
+        data.resize(BOOKMARK_NAME_SIZE, 0);
+        data.write_u32(self.offset)?;
+        Ok(data)
+    }
+}
+
+/// A classic Palm `TEXt`/`REAd` ebook: record 0 holds a PalmDOC header without
+/// a MOBI header, followed by the text records and one record per bookmark.
+///
+/// The text is kept as raw bytes since Palm era documents are usually not
+/// UTF-8.
+#[derive(Debug, Clone)]
+pub struct PalmDOC {
+    pub name: String,
+    pub text: Vec<u8>,
+    pub bookmarks: Vec<Bookmark>,
+    /// 1 for uncompressed text, 2 for PalmDOC compression.
+    pub compression: u16,
+    /// Last reading position, stored in place of the encryption type.
+    pub position: u32,
+}
+
+impl PalmDOC {
+    pub fn new(name: impl Into<String>, text: impl Into<Vec<u8>>) -> Self {
+        Self {
+            name: name.into(),
+            text: text.into(),
+            bookmarks: vec![],
+            compression: 2,
+            position: 0,
+        }
+    }
+
+    pub fn add_bookmark(&mut self, name: impl Into<String>, offset: u32) {
+        self.bookmarks.push(Bookmark {
+            name: name.into(),
+            offset,
+        });
+    }
+
+    /// Reads a book with the default [`ParseLimits`].
+    pub fn from_bytes<R: Read + Seek>(reader: &mut R) -> Result<Self, MobiError> {
+        Self::from_bytes_with_limits(reader, &ParseLimits::default())
+    }
+
+    pub fn from_bytes_with_limits<R: Read + Seek>(
+        reader: &mut R,
+        limits: &ParseLimits,
+    ) -> Result<Self, MobiError> {
+        Self::from_source_with_limits(&PDB::from_bytes_with_limits(reader, &limits.pdb)?, limits)
+    }
+
+    pub fn from_source<P: RecordSource>(pdb: &P) -> Result<Self, MobiError> {
+        Self::from_source_with_limits(pdb, &ParseLimits::default())
+    }
+
+    pub fn from_source_with_limits<P: RecordSource>(
+        pdb: &P,
+        limits: &ParseLimits,
+    ) -> Result<Self, MobiError> {
+        let header = pdb.header();
+        check_magic("TEXt", header.type_.as_bytes())?;
+
+        let record0 = pdb.record_data(0)?;
+        let palmdoc_header = PalmDOCHeader::from_bytes(&mut Cursor::new(record0.as_ref()))?;
+        let position = (&record0[12..16]).read_u32()?;
+
+        let text = decompress_text(
+            palmdoc_header.compression,
+            palmdoc_header.text_length,
+            (1..=palmdoc_header.record_count).map(|i| Ok(pdb.record_data(i)?)),
+            limits.max_decompressed_size,
+        )?;
+
+        let mut bookmarks = vec![];
+        for i in palmdoc_header.record_count as u32 + 1..header.number_of_records as u32 {
+            let record = pdb.record_data(i as u16)?;
+            if record.len() == BOOKMARK_RECORD_SIZE {
+                bookmarks.push(Bookmark::from_bytes(&record)?);
+            }
+        }
+
+        Ok(PalmDOC {
+            name: header.name.clone(),
+            text,
+            bookmarks,
+            compression: palmdoc_header.compression,
+            position,
+        })
+    }
+
+    pub fn to_pdb(&self) -> Result<PDB, MobiError> {
+        let text_records = self
+            .text
+            .chunks(TEXT_RECORD_SIZE)
+            .map(|chunk| match self.compression {
+                1 => Ok(chunk.to_vec()),
+                2 => Ok(palmdoc_compression::compress(chunk)),
+                compression => Err(MobiError::UnsupportedCompression(compression)),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let palmdoc_header = PalmDOCHeader {
+            compression: self.compression,
+            text_length: self.text.len() as u32,
+            record_count: text_records.len() as u16,
+            record_size: TEXT_RECORD_SIZE as u16,
+            encryption_type: 0,
+        };
+        let mut record0 = palmdoc_header.to_bytes()?;
+        record0.truncate(12);
+        record0.write_u32(self.position)?;
+
+        let mut name = self.name.clone();
+        while name.len() > 31 {
+            name.pop();
+        }
+        // Palm OS reads the name as Windows-1252.
+        let name_encoding = match TextEncoding::Cp1252.encode(&name) {
+            Some(_) => TextEncoding::Cp1252,
+            None => TextEncoding::Utf8,
+        };
+        let mut pdb = PDBBuilder::new()
+            .name(name)
+            .name_encoding(name_encoding)
+            .type_("TEXt")
+            .creator("REAd")
+            .creation_time(None)
+            .modification_time(None)
+            .last_backup_date(None)
+            .build()?;
+
+        pdb.add_record(record0);
+        for record in text_records {
+            pdb.add_record(record);
+        }
+        for bookmark in &self.bookmarks {
+            pdb.add_record(bookmark.to_bytes()?);
+        }
+
+        Ok(pdb)
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, MobiError> {
+        Ok(self.to_pdb()?.to_bytes()?)
+    }
+
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<(), MobiError> {
+        Ok(self.to_pdb()?.write_to(writer)?)
+    }
+
+    /// UTF-8 if the text is valid UTF-8, Windows-1252 like on Palm OS
+    /// otherwise.
+    pub fn text_encoding(&self) -> TextEncoding {
+        match std::str::from_utf8(&self.text) {
+            Ok(_) => TextEncoding::Utf8,
+            Err(_) => TextEncoding::Cp1252,
+        }
+    }
+
+    /// Converts the plain text into HTML, with an anchor at every bookmark.
+    pub fn to_html(&self) -> String {
+        let mut bookmarks: Vec<(usize, &Bookmark)> = self.bookmarks.iter().enumerate().collect();
+        bookmarks.sort_by_key(|(_, bookmark)| bookmark.offset);
+
+        let encoding = self.text_encoding();
+        let mut html = String::from("<html><head></head><body><p>");
+        let mut start = 0;
+        for (i, bookmark) in bookmarks {
+            let offset = (bookmark.offset as usize).clamp(start, self.text.len());
+            push_text(&mut html, encoding, &self.text[start..offset]);
+            html.push_str(&format!(r#"<a id="bookmark{i}"></a>"#));
+            start = offset;
+        }
+        push_text(&mut html, encoding, &self.text[start..]);
+        html.push_str("</p></body></html>");
+
+        html
+    }
+
+    /// Prepares a MOBI conversion of the book, see [`PalmDOC::to_html`]. The
+    /// bookmarks become the table of contents.
+    pub fn to_mobi(&self) -> MobiBook {
+        let mut book = MobiBook::new(self.name.clone());
+        book.html = self.to_html();
+        book.toc = self
+            .bookmarks
+            .iter()
+            .enumerate()
+            .map(|(i, bookmark)| TocEntry {
+                title: bookmark.name.clone(),
+                anchor: format!("bookmark{i}"),
+            })
+            .collect();
+        book
+    }
+}
+
+/// Appends a piece of the text, turning line breaks into paragraphs.
+fn push_text(html: &mut String, encoding: TextEncoding, text: &[u8]) {
+    let text = encoding.decode(text);
+    let mut lines = text.split('\n');
+    if let Some(line) = lines.next() {
+        html.push_str(&escape_html(line.trim_end_matches('\r')));
+    }
+    for line in lines {
+        html.push_str("</p><p>");
+        html.push_str(&escape_html(line.trim_end_matches('\r')));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mobi::MOBI;
+
+    fn test_doc() -> PalmDOC {
+        let mut text = b"Chapter 1\nIt was a dark & stormy night.\n".to_vec();
+        text.extend(std::iter::repeat_n(b'x', TEXT_RECORD_SIZE));
+        text.extend_from_slice(b"\nChapter 2\nThe end.");
+
+        let mut doc = PalmDOC::new("Test Doc", text);
+        doc.add_bookmark("Chapter 1", 0);
+        doc.add_bookmark("A very long bookmark name", 4137);
+        doc.position = 42;
+        doc
+    }
+
+    #[test]
+    fn test_palmdoc_round_trip() {
+        for compression in [1, 2] {
+            let mut doc = test_doc();
+            doc.compression = compression;
+            let bytes = doc.to_bytes().expect("Failed to write PalmDOC");
+            assert_eq!(&bytes[60..68], b"TEXtREAd");
+
+            let read =
+                PalmDOC::from_bytes(&mut Cursor::new(bytes)).expect("Failed to read PalmDOC");
+            assert_eq!(read.name, "Test Doc");
+            assert_eq!(read.text, doc.text);
+            assert_eq!(read.compression, compression);
+            assert_eq!(read.position, 42);
+            assert_eq!(read.bookmarks[0], doc.bookmarks[0]);
+            assert_eq!(read.bookmarks[1].name, "A very long boo");
+            assert_eq!(read.bookmarks[1].offset, 4137);
+        }
+    }
+
+    #[test]
+    fn test_palmdoc_to_mobi() {
+        let book = test_doc().to_mobi();
+        let bytes = book.to_bytes().expect("Failed to write MOBI");
+        let mobi = MOBI::from_bytes(&mut Cursor::new(&bytes)).expect("Failed to read MOBI");
+
+        let text = mobi.text().unwrap();
+        assert!(text.contains("dark &amp; stormy"));
+        let toc = mobi.guide().unwrap()[0].filepos;
+        assert!(text[toc..].starts_with(r#"<div id="mobi-toc">"#));
+
+        let read = MobiBook::from_bytes(&mut Cursor::new(bytes)).unwrap();
+        assert_eq!(read.toc, book.toc);
+        assert_eq!(read.html, book.html);
+    }
+
+    #[test]
+    fn test_not_palmdoc() {
+        let mut book = MobiBook::new("Test");
+        book.html = "<p>Hi</p>".to_owned();
+        let bytes = book.to_bytes().unwrap();
+        assert!(PalmDOC::from_bytes(&mut Cursor::new(bytes)).is_err());
+    }
+}
@@ -0,0 +1,288 @@
+use crate::drm::DrmInfo;
+use crate::mobi_header::{EXTH_FLAG, MOBIHeader, NULL_INDEX};
+use crate::mobi_writer::EOF_RECORD;
+use crate::palmdoc_header::PalmDOCHeader;
+use byyte::be::ByteReader;
+use palm_database::pdb_ref::PdbRef;
+use std::io::Cursor;
+
+pub use palm_database::validate::{Finding, Severity};
+
+/// Largest image record Kindle devices reliably display.
+pub const MAX_IMAGE_SIZE: usize = 127 * 1024;
+
+/// Checks the PDB structure of a book and the record pointers in its headers.
+/// The MOBI checks are skipped if the records cannot be located at all.
+pub fn validate(data: &[u8]) -> Vec<Finding> {
+    let mut findings = palm_database::validate::validate(data);
+    let Ok(pdb) = PdbRef::with_limits(data, &palm_database::ParseLimits::unlimited()) else {
+        return findings;
+    };
+    let record_count = pdb.header.number_of_records as u32;
+    let Some(record0) = pdb.read_record(0) else {
+        findings.push(Finding::error(None, "The book has no records"));
+        return findings;
+    };
+
+    let palmdoc_header = match PalmDOCHeader::from_bytes(&mut Cursor::new(record0)) {
+        Ok(header) => header,
+        Err(err) => {
+            findings.push(Finding::error(
+                Some(0),
+                format!("Unreadable PalmDOC header: {err}"),
+            ));
+            return findings;
+        }
+    };
+    if palmdoc_header.record_count as u32 >= record_count {
+        findings.push(Finding::error(
+            Some(0),
+            format!(
+                "PalmDOC header lists {} text records, but there are only {record_count} records",
+                palmdoc_header.record_count
+            ),
+        ));
+    }
+
+    let header = match MOBIHeader::from_bytes(&mut Cursor::new(&record0[16.min(record0.len())..])) {
+        Ok(header) => header,
+        Err(err) => {
+            findings.push(Finding::error(
+                Some(0),
+                format!("Unreadable MOBI header: {err}"),
+            ));
+            return findings;
+        }
+    };
+
+    let drm = DrmInfo::from_headers(&palmdoc_header, &header);
+    if drm.is_encrypted() {
+        findings.push(Finding::warning(
+            Some(0),
+            format!("The text is encrypted ({drm})"),
+        ));
+    }
+
+    let header_end = 16 + header.header_length as usize;
+    if header_end > record0.len() {
+        findings.push(Finding::error(
+            Some(0),
+            format!(
+                "Record is {} bytes, too short for a MOBI header of {} bytes",
+                record0.len(),
+                header.header_length
+            ),
+        ));
+    }
+    if header.full_name_offset as u64 + header.full_name_length as u64 > record0.len() as u64 {
+        findings.push(Finding::error(
+            Some(0),
+            "Full name extends past the end of the record",
+        ));
+    }
+    if header.exth_flags & EXTH_FLAG != 0 {
+        validate_exth(record0.get(header_end..).unwrap_or_default(), &mut findings);
+    }
+
+    let record = |index: u32| {
+        pdb.read_record(index as u16)
+            .filter(|_| index < record_count)
+    };
+    let first_text_free = palmdoc_header.record_count as u32 + 1;
+    if header.first_non_book_index != NULL_INDEX
+        && (header.first_non_book_index < first_text_free
+            || header.first_non_book_index > record_count)
+    {
+        findings.push(Finding::error(
+            Some(0),
+            format!(
+                "first_non_book_index is {}, but the text ends at record {}",
+                header.first_non_book_index, palmdoc_header.record_count
+            ),
+        ));
+    }
+
+    let images: Vec<u32> = (first_text_free..record_count)
+        .filter(|&i| record(i).is_some_and(is_image))
+        .collect();
+    if header.first_image_index != NULL_INDEX && !images.is_empty() {
+        if record(header.first_image_index).is_none_or(|data| !is_image(data)) {
+            findings.push(Finding::error(
+                Some(0),
+                format!(
+                    "first_image_index is {}, which is not an image record",
+                    header.first_image_index
+                ),
+            ));
+        } else if images[0] < header.first_image_index {
+            findings.push(Finding::warning(
+                Some(0),
+                format!(
+                    "first_image_index is {}, but record {} is already an image",
+                    header.first_image_index, images[0]
+                ),
+            ));
+        }
+    }
+
+    let last_content = header.last_content_record_number as u32;
+    if last_content >= record_count {
+        findings.push(Finding::error(
+            Some(0),
+            format!("last_content_record_number {last_content} is not a record"),
+        ));
+    } else if last_content < header.first_content_record_number as u32 {
+        findings.push(Finding::error(
+            Some(0),
+            format!(
+                "last_content_record_number {last_content} is before first_content_record_number {}",
+                header.first_content_record_number
+            ),
+        ));
+    } else if let Some(&last_image) = images.last()
+        && last_image > last_content
+    {
+        findings.push(Finding::error(
+            Some(0),
+            format!(
+                "last_content_record_number is {last_content}, but record {last_image} is an image"
+            ),
+        ));
+    }
+
+    for (name, index) in [
+        ("FCIS", header.fcis_record_number),
+        ("FLIS", header.flis_record_number),
+        ("INDX", header.indx_record_offset),
+    ] {
+        if index == NULL_INDEX {
+            continue;
+        }
+        if !record(index).is_some_and(|data| data.starts_with(name.as_bytes())) {
+            findings.push(Finding::error(
+                Some(0),
+                format!("{name} record number {index} does not point to a {name} record"),
+            ));
+        }
+    }
+
+    for &i in &images {
+        let size = record(i).map_or(0, <[u8]>::len);
+        if size > MAX_IMAGE_SIZE {
+            findings.push(Finding::warning(
+                Some(i as u16),
+                format!("Image is {size} bytes, devices may not show images over {MAX_IMAGE_SIZE}"),
+            ));
+        }
+    }
+
+    if record(record_count - 1) != Some(&EOF_RECORD[..]) {
+        findings.push(Finding::warning(
+            None,
+            "The last record is not an EOF record",
+        ));
+    }
+
+    findings
+}
+
+fn validate_exth(data: &[u8], findings: &mut Vec<Finding>) {
+    if !data.starts_with(b"EXTH") || data.len() < 12 {
+        findings.push(Finding::error(
+            Some(0),
+            "EXTH flag is set, but there is no EXTH header",
+        ));
+        return;
+    }
+    let mut reader = &data[4..];
+    let (Ok(header_length), Ok(count)) = (reader.read_u32(), reader.read_u32()) else {
+        return;
+    };
+
+    let mut length = 12u64;
+    for _ in 0..count {
+        let Some(record) = data.get(length as usize..length as usize + 8) else {
+            findings.push(Finding::error(
+                Some(0),
+                format!("EXTH header lists {count} records, but the record ends first"),
+            ));
+            return;
+        };
+        let record_length = u32::from_be_bytes([record[4], record[5], record[6], record[7]]);
+        if record_length < 8 {
+            findings.push(Finding::error(
+                Some(0),
+                format!("EXTH record length {record_length} is shorter than its header"),
+            ));
+            return;
+        }
+        length += record_length as u64;
+    }
+
+    if length > data.len() as u64 {
+        findings.push(Finding::error(
+            Some(0),
+            "EXTH records extend past the end of the record",
+        ));
+    } else if header_length as u64 != length && header_length as u64 != length.next_multiple_of(4) {
+        findings.push(Finding::warning(
+            Some(0),
+            format!("EXTH header length is {header_length}, but its records add up to {length}"),
+        ));
+    }
+}
+
+pub(crate) fn is_image(data: &[u8]) -> bool {
+    data.starts_with(&[0xFF, 0xD8, 0xFF])
+        || data.starts_with(b"\x89PNG")
+        || data.starts_with(b"GIF8")
+        || data.starts_with(b"BM")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mobi_writer::MobiWriter;
+
+    fn test_book(image: Vec<u8>) -> Vec<u8> {
+        let mut writer = MobiWriter::new("Test".to_owned());
+        writer.set_content("<p>Hello</p>".to_owned());
+        writer.add_image(image);
+        writer.to_bytes().unwrap()
+    }
+
+    /// Overwrites a field of the MOBI header in record 0.
+    fn patch(bytes: &mut [u8], field_offset: usize, value: &[u8]) {
+        let record0 = u32::from_be_bytes(bytes[78..82].try_into().unwrap()) as usize;
+        let start = record0 + 16 + field_offset;
+        bytes[start..start + value.len()].copy_from_slice(value);
+    }
+
+    #[test]
+    fn test_validate_mobi() {
+        // Text in record 1, the image in 2, then FLIS, FCIS and EOF.
+        let mut bytes = test_book(vec![0xFF, 0xD8, 0xFF, 0xE0]);
+        assert_eq!(validate(&bytes), vec![]);
+
+        patch(&mut bytes, 92, &3u32.to_be_bytes());
+        patch(&mut bytes, 184, &3u32.to_be_bytes());
+        let findings = validate(&bytes);
+        assert_eq!(findings.len(), 2);
+        assert!(findings[0].message.starts_with("first_image_index"));
+        assert!(findings[1].message.starts_with("FCIS"));
+
+        let len = bytes.len();
+        let findings = validate(&bytes[..len - 4]);
+        assert!(findings.iter().any(|f| f.message.contains("EOF")));
+    }
+
+    #[test]
+    fn test_validate_large_image() {
+        let mut image = vec![0xFF, 0xD8, 0xFF, 0xE0];
+        image.resize(MAX_IMAGE_SIZE + 1, 0);
+        let findings = validate(&test_book(image));
+        assert!(findings.iter().any(|finding| finding.record == Some(2)
+            && finding.severity == Severity::Warning
+            && finding.message.starts_with("Image is")));
+    }
+}
@@ -1,3 +1,41 @@
-fn main() {
+use palm_database::PDBHeader;
+use palm_database::lazy::LazyPdb;
+use palm_database::prc::PRC;
+use std::fs::File;
+use std::io::{Seek, SeekFrom};
 
-}
\ No newline at end of file
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let path = std::env::args().nth(1).unwrap_or("test.pdb".to_owned());
+    let mut file = File::open(path)?;
+
+    if PDBHeader::from_bytes(&mut file)?.is_resource_db() {
+        file.seek(SeekFrom::Start(0))?;
+        let prc = PRC::from_bytes(&mut file)?;
+
+        println!("{:#?}", prc.header);
+        for resource in &prc.resources {
+            println!(
+                "{:?} {:5}: {:6} bytes",
+                resource.type_,
+                resource.id,
+                resource.data.len()
+            );
+        }
+        return Ok(());
+    }
+
+    let pdb = LazyPdb::new(file)?;
+
+    println!("{:#?}", pdb.header);
+    for (i, record) in pdb.records.iter().enumerate() {
+        println!(
+            "{i:5}: offset {:#010x}, {:6} bytes, unique ID {:#08x}, {:?}",
+            record.data_offset,
+            pdb.record_len(i as u16).unwrap_or(0),
+            record.unique_id,
+            record.attributes
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,68 @@
+use bitflags::bitflags;
+
+bitflags! {
+    /// The attribute byte of a record list entry. The low four bits hold the
+    /// record's category rather than flags.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+    pub struct RecordAttributes: u8 {
+        const DELETE = 0x80;
+        const DIRTY = 0x40;
+        const BUSY = 0x20;
+        const SECRET = 0x10;
+        const CATEGORY = 0x0F;
+    }
+}
+
+bitflags! {
+    /// The attribute field of the database header.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+    pub struct DatabaseAttributes: u16 {
+        /// Resource database (`.prc`) rather than record database.
+        const RESOURCE_DB = 0x0001;
+        const READ_ONLY = 0x0002;
+        const APP_INFO_DIRTY = 0x0004;
+        const BACKUP = 0x0008;
+        const OK_TO_INSTALL_NEWER = 0x0010;
+        const RESET_AFTER_INSTALL = 0x0020;
+        const COPY_PREVENTION = 0x0040;
+        const STREAM = 0x0080;
+        const HIDDEN = 0x0100;
+        const LAUNCHABLE_DATA = 0x0200;
+        const RECYCLABLE = 0x0400;
+        const BUNDLE = 0x0800;
+        const OPEN = 0x8000;
+    }
+}
+
+impl RecordAttributes {
+    pub fn category(self) -> u8 {
+        (self & Self::CATEGORY).bits()
+    }
+
+    pub fn with_category(self, category: u8) -> Self {
+        (self - Self::CATEGORY) | Self::from_bits_retain(category & Self::CATEGORY.bits())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_category() {
+        let attributes = RecordAttributes::DIRTY.with_category(5);
+        assert_eq!(attributes.bits(), 0x45);
+        assert_eq!(attributes.category(), 5);
+        assert!(attributes.contains(RecordAttributes::DIRTY));
+        assert_eq!(attributes.with_category(2).bits(), 0x42);
+    }
+
+    #[test]
+    fn test_database_attributes_debug() {
+        let attributes = DatabaseAttributes::from_bits_retain(0x0208);
+        assert_eq!(
+            format!("{attributes:?}"),
+            "DatabaseAttributes(BACKUP | LAUNCHABLE_DATA)"
+        );
+    }
+}
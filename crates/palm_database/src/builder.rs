@@ -1,59 +1,50 @@
-use crate::{PDBHeader, PDBRecord, PDB};
-
-#[derive(Debug)]
-pub enum PDBError {
-    IoError(std::io::Error),
-    InvalidData(String),
-    MissingField(String),
-}
+use crate::encoding::TextEncoding;
+use crate::timestamp::{Epoch, PalmTimestamp};
+use crate::{
+    DatabaseAttributes, MAX_UNIQUE_ID, PDB, PDBError, PDBHeader, Record, RecordAttributes,
+};
 
+#[derive(Default)]
 pub struct PDBBuilder {
     name: Option<String>,
-    attributes: u16,
+    name_encoding: TextEncoding,
+    attributes: DatabaseAttributes,
     version: u16,
-    creation_time: Option<chrono::NaiveDateTime>,
-    modification_time: Option<chrono::NaiveDateTime>,
-    last_backup_date: Option<chrono::NaiveDateTime>,
+    // The outer `None` means the timestamp was not set and gets its default.
+    creation_time: Option<Option<chrono::NaiveDateTime>>,
+    modification_time: Option<Option<chrono::NaiveDateTime>>,
+    last_backup_date: Option<Option<chrono::NaiveDateTime>>,
+    epoch: Epoch,
     modification_number: u32,
-    app_info_id: u32,
-    sort_info_id: u32,
+    app_info: Option<Vec<u8>>,
+    sort_info: Option<Vec<u8>>,
     type_: Option<String>,
     creator: Option<String>,
-    unique_id_seed: u32,
+    unique_id_seed: Option<u32>,
     next_record_list_id: u32,
-    number_of_records: u16,
     records: Vec<(u32, u8, Vec<u8>)>, // (unique_id, attributes, data)
 }
 
 impl PDBBuilder {
     pub fn new() -> Self {
-        PDBBuilder {
-            name: None,
-            attributes: 0,
-            version: 0,
-            creation_time: None,
-            modification_time: None,
-            last_backup_date: None,
-            modification_number: 0,
-            app_info_id: 0,
-            sort_info_id: 0,
-            type_: None,
-            creator: None,
-            unique_id_seed: 0,
-            next_record_list_id: 0,
-            number_of_records: 0,
-            records: Vec::new(),
-        }
+        Self::default()
     }
 
+    /// The database name, at most 31 bytes so it fits the 32 byte field with
+    /// its terminator.
     pub fn name(mut self, name: impl Into<String>) -> Self {
-        let name = name.into();
-        assert!(name.len() <= 31, "Name must be at most 31 characters long");
-        self.name = Some(name);
+        self.name = Some(name.into());
+        self
+    }
+
+    /// How the name is stored, UTF-8 by default. Palm OS devices expect
+    /// [`TextEncoding::Cp1252`].
+    pub fn name_encoding(mut self, name_encoding: TextEncoding) -> Self {
+        self.name_encoding = name_encoding;
         self
     }
 
-    pub fn attributes(mut self, attributes: u16) -> Self {
+    pub fn attributes(mut self, attributes: DatabaseAttributes) -> Self {
         self.attributes = attributes;
         self
     }
@@ -63,17 +54,78 @@ impl PDBBuilder {
         self
     }
 
+    /// Defaults to the time the database is built, `None` leaves it unset.
+    pub fn creation_time(
+        mut self,
+        creation_time: impl Into<Option<chrono::NaiveDateTime>>,
+    ) -> Self {
+        self.creation_time = Some(creation_time.into());
+        self
+    }
+
+    /// Defaults to the time the database is built, `None` leaves it unset.
+    pub fn modification_time(
+        mut self,
+        modification_time: impl Into<Option<chrono::NaiveDateTime>>,
+    ) -> Self {
+        self.modification_time = Some(modification_time.into());
+        self
+    }
+
+    /// Defaults to unset, meaning the database was never backed up.
+    pub fn last_backup_date(
+        mut self,
+        last_backup_date: impl Into<Option<chrono::NaiveDateTime>>,
+    ) -> Self {
+        self.last_backup_date = Some(last_backup_date.into());
+        self
+    }
+
+    /// The epoch the timestamps are written in, [`Epoch::Mac1904`] by default.
+    pub fn epoch(mut self, epoch: Epoch) -> Self {
+        self.epoch = epoch;
+        self
+    }
+
+    pub fn modification_number(mut self, modification_number: u32) -> Self {
+        self.modification_number = modification_number;
+        self
+    }
+
+    /// The AppInfo block. Its offset (`app_info_id`) is filled in when the
+    /// database is written.
+    pub fn app_info(mut self, app_info: impl Into<Vec<u8>>) -> Self {
+        self.app_info = Some(app_info.into());
+        self
+    }
+
+    /// The SortInfo block. Its offset (`sort_info_id`) is filled in when the
+    /// database is written.
+    pub fn sort_info(mut self, sort_info: impl Into<Vec<u8>>) -> Self {
+        self.sort_info = Some(sort_info.into());
+        self
+    }
+
+    /// Four character type code, e.g. `BOOK`.
     pub fn type_(mut self, type_: impl Into<String>) -> Self {
-        let type_ = type_.into();
-        assert_eq!(type_.len(), 4, "Type must be exactly 4 characters long");
-        self.type_ = Some(type_);
+        self.type_ = Some(type_.into());
         self
     }
 
+    /// Four character creator code, e.g. `MOBI`.
     pub fn creator(mut self, creator: impl Into<String>) -> Self {
-        let creator = creator.into();
-        assert_eq!(creator.len(), 4, "Creator must be exactly 4 characters long");
-        self.creator = Some(creator);
+        self.creator = Some(creator.into());
+        self
+    }
+
+    /// Defaults to one past the highest unique ID of the added records.
+    pub fn unique_id_seed(mut self, unique_id_seed: u32) -> Self {
+        self.unique_id_seed = Some(unique_id_seed);
+        self
+    }
+
+    pub fn next_record_list_id(mut self, next_record_list_id: u32) -> Self {
+        self.next_record_list_id = next_record_list_id;
         self
     }
 
@@ -82,32 +134,91 @@ impl PDBBuilder {
         self
     }
 
+    fn check_code(field: &str, value: Option<String>) -> Result<String, PDBError> {
+        let value = value.ok_or(PDBError::MissingField(field.to_owned()))?;
+        if value.len() != 4 {
+            return Err(PDBError::InvalidData(format!(
+                "{field} must be exactly 4 bytes long, got {value:?}"
+            )));
+        }
+        Ok(value)
+    }
+
     pub fn build(self) -> Result<PDB, PDBError> {
-        let epoch = chrono::NaiveDate::from_ymd_opt(1904, 1, 1)
-            .and_then(|t| t.and_hms_opt(0, 0, 0))
-            .unwrap();
+        let now = chrono::Utc::now().naive_utc();
+        let timestamp = |datetime: Option<Option<chrono::NaiveDateTime>>, default| {
+            datetime
+                .unwrap_or(default)
+                .map(|datetime| PalmTimestamp::new(datetime, self.epoch))
+        };
+
         let name = self.name.ok_or(PDBError::MissingField("name".to_owned()))?;
+        let Some(encoded_name) = self.name_encoding.encode(&name) else {
+            return Err(PDBError::InvalidData(format!(
+                "name {name:?} cannot be stored as {:?}",
+                self.name_encoding
+            )));
+        };
+        if encoded_name.len() > 31 {
+            return Err(PDBError::InvalidData(format!(
+                "name must be at most 31 bytes long, got {} bytes",
+                encoded_name.len()
+            )));
+        }
+        let type_ = Self::check_code("type", self.type_)?;
+        let creator = Self::check_code("creator", self.creator)?;
 
-        Ok(PDB {
-            header: PDBHeader {
+        if self.records.len() > u16::MAX as usize {
+            return Err(PDBError::InvalidData(format!(
+                "a database can hold at most {} records, got {}",
+                u16::MAX,
+                self.records.len()
+            )));
+        }
+        if let Some((unique_id, _, _)) = self.records.iter().find(|(id, _, _)| *id > MAX_UNIQUE_ID)
+        {
+            return Err(PDBError::UniqueIdOverflow(*unique_id));
+        }
+
+        let unique_id_seed = self.unique_id_seed.unwrap_or_else(|| {
+            self.records
+                .iter()
+                .map(|(unique_id, _, _)| unique_id + 1)
+                .max()
+                .unwrap_or(0)
+        });
+
+        let mut pdb = PDB::from_records(
+            PDBHeader {
                 name,
+                name_encoding: self.name_encoding,
                 attributes: self.attributes,
                 version: self.version,
-                creation_time: self.creation_time.unwrap_or(chrono::Utc::now().naive_utc()),
-                modification_time: self.modification_time.unwrap_or(chrono::Utc::now().naive_utc()),
-                last_backup_date: self.modification_time.unwrap_or(epoch),
+                creation_time: timestamp(self.creation_time, Some(now)),
+                modification_time: timestamp(self.modification_time, Some(now)),
+                last_backup_date: timestamp(self.last_backup_date, None),
                 modification_number: self.modification_number,
-                app_info_id: self.app_info_id,
-                sort_info_id: self.sort_info_id,
-                type_: self.type_.ok_or(PDBError::MissingField("type".to_owned()))?,
-                creator: self.creator.ok_or(PDBError::MissingField("creator".to_owned()))?,
-                unique_id_seed: self.unique_id_seed,
+                app_info_id: 0,
+                sort_info_id: 0,
+                type_,
+                creator,
+                unique_id_seed,
                 next_record_list_id: self.next_record_list_id,
                 number_of_records: self.records.len() as u16,
             },
-            records: vec![],
-            record_data: self.records.iter().map(|(_, _, data)| data.clone()).collect(),
-        })
+            self.records
+                .into_iter()
+                .map(|(unique_id, attributes, data)| Record {
+                    attributes: RecordAttributes::from_bits_retain(attributes),
+                    unique_id,
+                    data,
+                })
+                .collect(),
+        );
+        pdb.app_info = self.app_info;
+        pdb.sort_info = self.sort_info;
+
+        Ok(pdb)
     }
 }
 
@@ -119,7 +230,7 @@ mod tests {
     fn test_pdb_builder() {
         let pdb = PDBBuilder::new()
             .name("test".to_owned())
-            .attributes(0)
+            .attributes(DatabaseAttributes::empty())
             .version(0)
             .type_("BOOK")
             .creator("MOBI")
@@ -129,9 +240,66 @@ mod tests {
             .expect("Failed to build PDB");
 
         assert_eq!(pdb.header.name, "test");
-        assert_eq!(pdb.records.len(), 2);
-        assert_eq!(pdb.record_data.len(), 2);
-        assert_eq!(pdb.record_data[0], b"Record 1 data");
-        assert_eq!(pdb.record_data[1], b"Record 2 data");
+        assert_eq!(pdb.records().len(), 2);
+        assert_eq!(pdb.read_record(0).unwrap(), b"Record 1 data");
+        assert_eq!(pdb.read_record(1).unwrap(), b"Record 2 data");
+        assert_eq!(pdb.record(1).unwrap().unique_id, 2);
+        assert_eq!(pdb.record(1).unwrap().attributes.category(), 1);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_pdb_builder_header_fields() {
+        let time = chrono::DateTime::from_timestamp(1_000_000_000, 0)
+            .unwrap()
+            .naive_utc();
+        let pdb = PDBBuilder::new()
+            .name("test")
+            .type_("BOOK")
+            .creator("MOBI")
+            .creation_time(time)
+            .modification_time(time)
+            .last_backup_date(time)
+            .modification_number(7)
+            .next_record_list_id(3)
+            .app_info(b"app info".to_vec())
+            .add_record(8, 0, b"data")
+            .build()
+            .expect("Failed to build PDB");
+
+        let time = Some(PalmTimestamp::new(time, Epoch::Mac1904));
+        assert_eq!(pdb.header.creation_time, time);
+        assert_eq!(pdb.header.modification_time, time);
+        assert_eq!(pdb.header.last_backup_date, time);
+        assert_eq!(pdb.header.modification_number, 7);
+        assert_eq!(pdb.header.next_record_list_id, 3);
+        assert_eq!(pdb.header.unique_id_seed, 9);
+        assert_eq!(pdb.header.number_of_records, 1);
+        assert_eq!(pdb.app_info.as_deref(), Some(&b"app info"[..]));
+    }
+
+    #[test]
+    fn test_pdb_builder_validation() {
+        let builder = || PDBBuilder::new().name("test").type_("BOOK").creator("MOBI");
+
+        assert!(matches!(
+            PDBBuilder::new().type_("BOOK").creator("MOBI").build(),
+            Err(PDBError::MissingField(_))
+        ));
+        assert!(matches!(
+            builder().name("x".repeat(32)).build(),
+            Err(PDBError::InvalidData(_))
+        ));
+        assert!(matches!(
+            builder().type_("BOOKS").build(),
+            Err(PDBError::InvalidData(_))
+        ));
+        assert!(matches!(
+            builder().creator("MO").build(),
+            Err(PDBError::InvalidData(_))
+        ));
+        assert!(matches!(
+            builder().add_record(MAX_UNIQUE_ID + 1, 0, b"").build(),
+            Err(PDBError::UniqueIdOverflow(_))
+        ));
+    }
+}
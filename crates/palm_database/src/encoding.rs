@@ -0,0 +1,107 @@
+/// Characters for bytes 0x80 to 0x9F, where Windows-1252 differs from
+/// Latin-1. The five bytes it leaves undefined map to the C1 control at the
+/// same code point, like browsers do, so that every byte round trips.
+const CP1252_HIGH: [char; 32] = [
+    '\u{20AC}', '\u{0081}', '\u{201A}', '\u{0192}', '\u{201E}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{02C6}', '\u{2030}', '\u{0160}', '\u{2039}', '\u{0152}', '\u{008D}', '\u{017D}', '\u{008F}',
+    '\u{0090}', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{02DC}', '\u{2122}', '\u{0161}', '\u{203A}', '\u{0153}', '\u{009D}', '\u{017E}', '\u{0178}',
+];
+
+/// A text encoding used by Palm databases. Palm OS itself used Windows-1252,
+/// a superset of Latin-1, while newer tools mostly write UTF-8.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TextEncoding {
+    #[default]
+    Utf8,
+    Cp1252,
+}
+
+impl TextEncoding {
+    /// The Windows code page number, as stored in e.g. the MOBI header.
+    pub fn from_code_page(code_page: u32) -> Option<Self> {
+        match code_page {
+            65001 => Some(TextEncoding::Utf8),
+            1252 => Some(TextEncoding::Cp1252),
+            _ => None,
+        }
+    }
+
+    pub fn code_page(self) -> u32 {
+        match self {
+            TextEncoding::Utf8 => 65001,
+            TextEncoding::Cp1252 => 1252,
+        }
+    }
+
+    /// Invalid UTF-8 is replaced with U+FFFD, Windows-1252 can't be invalid.
+    pub fn decode(self, bytes: &[u8]) -> String {
+        match self {
+            TextEncoding::Utf8 => String::from_utf8_lossy(bytes).into_owned(),
+            TextEncoding::Cp1252 => bytes.iter().map(|&b| decode_cp1252(b)).collect(),
+        }
+    }
+
+    /// Returns `None` if the text has characters the encoding can't represent.
+    pub fn encode(self, text: &str) -> Option<Vec<u8>> {
+        match self {
+            TextEncoding::Utf8 => Some(text.as_bytes().to_vec()),
+            TextEncoding::Cp1252 => text.chars().map(encode_cp1252).collect(),
+        }
+    }
+
+    /// Like [`TextEncoding::encode`], but writes `?` for characters the
+    /// encoding can't represent.
+    pub fn encode_lossy(self, text: &str) -> Vec<u8> {
+        match self {
+            TextEncoding::Utf8 => text.as_bytes().to_vec(),
+            TextEncoding::Cp1252 => text
+                .chars()
+                .map(|c| encode_cp1252(c).unwrap_or(b'?'))
+                .collect(),
+        }
+    }
+
+    pub fn can_encode(self, c: char) -> bool {
+        match self {
+            TextEncoding::Utf8 => true,
+            TextEncoding::Cp1252 => encode_cp1252(c).is_some(),
+        }
+    }
+}
+
+fn decode_cp1252(b: u8) -> char {
+    match b {
+        0x80..=0x9F => CP1252_HIGH[(b - 0x80) as usize],
+        _ => b as char,
+    }
+}
+
+fn encode_cp1252(c: char) -> Option<u8> {
+    match c as u32 {
+        0..0x80 | 0xA0..=0xFF => Some(c as u8),
+        _ => CP1252_HIGH
+            .iter()
+            .position(|&high| high == c)
+            .map(|i| 0x80 + i as u8),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cp1252() {
+        let bytes: Vec<u8> = (0..=255).collect();
+        let text = TextEncoding::Cp1252.decode(&bytes);
+        assert_eq!(TextEncoding::Cp1252.encode(&text), Some(bytes));
+
+        assert_eq!(
+            TextEncoding::Cp1252.decode(b"\x93caf\xe9\x94 \x80"),
+            "\u{201C}café\u{201D} €"
+        );
+        assert_eq!(TextEncoding::Cp1252.encode("日本"), None);
+        assert_eq!(TextEncoding::Cp1252.encode_lossy("€ 日本"), b"\x80 ??");
+    }
+}
@@ -0,0 +1,51 @@
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum PDBError {
+    #[error("I/O error: {0}")]
+    IoError(#[source] std::io::Error),
+    /// The data ended in the middle of the header or the record list.
+    #[error("Unexpected end of data")]
+    Truncated,
+    #[error("Invalid data: {0}")]
+    InvalidData(String),
+    #[error("Missing field: {0}")]
+    MissingField(String),
+    #[error(
+        "Record {record} starts at offset {offset}, past the end of the file ({file_len} bytes)"
+    )]
+    OffsetOutOfRange {
+        record: u16,
+        offset: u32,
+        file_len: u64,
+    },
+    #[error("Record {record} starts before the record preceding it")]
+    OffsetsOutOfOrder { record: u16 },
+    #[error("Record index {index} out of range ({count} records)")]
+    RecordOutOfRange { index: u16, count: usize },
+    #[error("Unique ID {0} does not fit in 24 bits")]
+    UniqueIdOverflow(u32),
+    #[error("Timestamp {0} cannot be stored as a Palm timestamp")]
+    TimestampOutOfRange(chrono::NaiveDateTime),
+    #[error("Expected {expected} records, but {written} were written")]
+    RecordCountMismatch { expected: u16, written: usize },
+    #[error("The {what} ({value}) exceeds the limit of {limit}")]
+    LimitExceeded {
+        what: &'static str,
+        value: u64,
+        limit: u64,
+    },
+    #[error("Resource database has no record list, read it as a PRC")]
+    ResourceDatabase,
+    #[error("Not a resource database, read it as a PDB")]
+    NotResourceDatabase,
+}
+
+impl From<std::io::Error> for PDBError {
+    fn from(err: std::io::Error) -> Self {
+        match err.kind() {
+            std::io::ErrorKind::UnexpectedEof => PDBError::Truncated,
+            _ => PDBError::IoError(err),
+        }
+    }
+}
@@ -0,0 +1,107 @@
+use crate::{
+    PDBError, PDBHeader, PDBRecord, ParseLimits, RecordSource, read_record_list, record_ranges,
+};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::io::{Read, Seek, SeekFrom};
+use std::ops::Range;
+
+/// A database whose header and record list are read up front, but whose record
+/// data is only read from `R` when asked for.
+///
+/// Useful when only a few records are needed, e.g. reading the metadata in
+/// record 0 of a large book.
+#[derive(Debug)]
+pub struct LazyPdb<R: Read + Seek> {
+    pub header: PDBHeader,
+    pub records: Vec<PDBRecord>,
+    ranges: Vec<Range<u64>>,
+    reader: RefCell<R>,
+}
+
+impl<R: Read + Seek> LazyPdb<R> {
+    /// Reads the header and record list with the default [`ParseLimits`].
+    pub fn new(reader: R) -> Result<Self, PDBError> {
+        Self::with_limits(reader, &ParseLimits::default())
+    }
+
+    pub fn with_limits(mut reader: R, limits: &ParseLimits) -> Result<Self, PDBError> {
+        reader.seek(SeekFrom::Start(0))?;
+        let header = PDBHeader::from_bytes(&mut reader)?;
+        let records = read_record_list(&mut reader, &header, limits)?;
+
+        let file_len = reader.seek(SeekFrom::End(0))?;
+        let ranges = record_ranges(&records, file_len, limits)?;
+
+        Ok(Self {
+            header,
+            records,
+            ranges,
+            reader: RefCell::new(reader),
+        })
+    }
+
+    pub fn record_len(&self, index: u16) -> Option<u64> {
+        self.ranges
+            .get(index as usize)
+            .map(|range| range.end - range.start)
+    }
+
+    pub fn read_record(&self, index: u16) -> Result<Vec<u8>, PDBError> {
+        let range = self
+            .ranges
+            .get(index as usize)
+            .ok_or(PDBError::RecordOutOfRange {
+                index,
+                count: self.ranges.len(),
+            })?;
+
+        let mut reader = self.reader.borrow_mut();
+        reader.seek(SeekFrom::Start(range.start))?;
+        let mut data = vec![0u8; (range.end - range.start) as usize];
+        reader.read_exact(&mut data)?;
+
+        Ok(data)
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader.into_inner()
+    }
+}
+
+impl<R: Read + Seek> RecordSource for LazyPdb<R> {
+    fn header(&self) -> &PDBHeader {
+        &self.header
+    }
+
+    fn record_data(&self, index: u16) -> Result<Cow<'_, [u8]>, PDBError> {
+        self.read_record(index).map(Cow::Owned)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::PDBBuilder;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_lazy_pdb() {
+        let mut pdb = PDBBuilder::new()
+            .name("TestDB")
+            .type_("TEST")
+            .creator("TEST")
+            .build()
+            .expect("Failed to build PDB");
+        pdb.add_record(b"Record 1 data".to_vec());
+        pdb.add_record(b"Record 2".to_vec());
+        let bytes = pdb.to_bytes().expect("Failed to convert PDB to bytes");
+
+        let lazy = LazyPdb::new(Cursor::new(bytes)).expect("Failed to read PDB");
+        assert_eq!(lazy.header.number_of_records, 2);
+        assert_eq!(lazy.record_len(1), Some(8));
+        assert_eq!(lazy.read_record(1).unwrap(), b"Record 2");
+        assert_eq!(lazy.read_record(0).unwrap(), b"Record 1 data");
+        assert!(lazy.read_record(2).is_err());
+    }
+}
@@ -1,26 +1,41 @@
+pub mod attributes;
 pub mod builder;
+pub mod encoding;
+pub mod error;
+pub mod lazy;
+pub mod limits;
+pub mod pdb_ref;
+pub mod prc;
 pub mod timestamp;
+pub mod validate;
+pub mod writer;
 
-use crate::timestamp::to_palm_timestamp;
+pub use crate::attributes::{DatabaseAttributes, RecordAttributes};
+pub use crate::encoding::TextEncoding;
+pub use crate::error::PDBError;
+pub use crate::limits::ParseLimits;
+pub use crate::timestamp::{Epoch, PalmTimestamp};
+use crate::timestamp::{from_palm_timestamp, to_palm_timestamp};
 use byyte::be::{ByteReader, ByteWriter};
-use std::io::{Error, ErrorKind, Read, Seek, SeekFrom, Write};
-
-pub fn parse_palm_timestamp(timestamp: u32) -> Result<chrono::NaiveDateTime, Error> {
-    let seconds = timestamp as i64;
-    let epoch = chrono::NaiveDate::from_ymd_opt(1904, 1, 1)
-        .and_then(|t| t.and_hms_opt(0, 0, 0))
-        .ok_or(Error::from(ErrorKind::InvalidData))?;
-    Ok(epoch + chrono::Duration::seconds(seconds))
+use std::borrow::Cow;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+
+pub fn parse_palm_timestamp(timestamp: u32) -> Option<PalmTimestamp> {
+    from_palm_timestamp(timestamp)
 }
 
 #[derive(Debug, Clone)]
 pub struct PDBHeader {
     pub name: String,
-    pub attributes: u16,
+    /// How the name is stored. Names that are not valid UTF-8 are read as
+    /// Windows-1252.
+    pub name_encoding: TextEncoding,
+    pub attributes: DatabaseAttributes,
     pub version: u16,
-    pub creation_time: chrono::NaiveDateTime,
-    pub modification_time: chrono::NaiveDateTime,
-    pub last_backup_date: chrono::NaiveDateTime,
+    pub creation_time: Option<PalmTimestamp>,
+    pub modification_time: Option<PalmTimestamp>,
+    pub last_backup_date: Option<PalmTimestamp>,
     pub modification_number: u32,
     pub app_info_id: u32,
     pub sort_info_id: u32,
@@ -32,14 +47,21 @@ pub struct PDBHeader {
 }
 
 impl PDBHeader {
-    pub fn from_bytes<R: Read>(reader: &mut R) -> std::io::Result<Self> {
-        let name = reader.read_cstr(32)?;
+    pub fn from_bytes<R: Read>(reader: &mut R) -> Result<Self, PDBError> {
+        let mut name = [0u8; 32];
+        reader.read_exact(&mut name)?;
+        let name = &name[..name.iter().position(|&b| b == 0).unwrap_or(name.len())];
+        let name_encoding = match std::str::from_utf8(name) {
+            Ok(_) => TextEncoding::Utf8,
+            Err(_) => TextEncoding::Cp1252,
+        };
+        let name = name_encoding.decode(name);
 
-        let attributes = reader.read_u16()?;
+        let attributes = DatabaseAttributes::from_bits_retain(reader.read_u16()?);
         let version = reader.read_u16()?;
-        let creation_time = parse_palm_timestamp(reader.read_u32()?)?;
-        let modification_time = parse_palm_timestamp(reader.read_u32()?)?;
-        let last_backup_date = parse_palm_timestamp(reader.read_u32()?)?;
+        let creation_time = parse_palm_timestamp(reader.read_u32()?);
+        let modification_time = parse_palm_timestamp(reader.read_u32()?);
+        let last_backup_date = parse_palm_timestamp(reader.read_u32()?);
         let modification_number = reader.read_u32()?;
         let app_info_id = reader.read_u32()?;
         let sort_info_id = reader.read_u32()?;
@@ -51,6 +73,7 @@ impl PDBHeader {
 
         Ok(PDBHeader {
             name,
+            name_encoding,
             attributes,
             version,
             creation_time,
@@ -67,13 +90,32 @@ impl PDBHeader {
         })
     }
 
-    pub fn to_bytes(&self) -> std::io::Result<Vec<u8>> {
-        let mut name = self.name.as_bytes().to_vec();
+    pub fn to_bytes(&self) -> Result<Vec<u8>, PDBError> {
+        let mut name = self.name_encoding.encode(&self.name).ok_or_else(|| {
+            PDBError::InvalidData(format!(
+                "Name {:?} cannot be stored as {:?}",
+                self.name, self.name_encoding
+            ))
+        })?;
+        if name.len() > 31 {
+            return Err(PDBError::InvalidData(format!(
+                "Name {:?} is longer than 31 bytes",
+                self.name
+            )));
+        }
+        for (field, code) in [("type", &self.type_), ("creator", &self.creator)] {
+            if code.len() != 4 {
+                return Err(PDBError::InvalidData(format!(
+                    "The {field} code {code:?} must be exactly 4 bytes"
+                )));
+            }
+        }
+
         name.resize(32, 0);
 
         let mut bytes = Vec::new();
-        bytes.write(&name)?;
-        bytes.write_u16(self.attributes)?;
+        bytes.write_all(&name)?;
+        bytes.write_u16(self.attributes.bits())?;
         bytes.write_u16(self.version)?;
         bytes.write_u32(to_palm_timestamp(self.creation_time)?)?;
         bytes.write_u32(to_palm_timestamp(self.modification_time)?)?;
@@ -81,146 +123,465 @@ impl PDBHeader {
         bytes.write_u32(self.modification_number)?;
         bytes.write_u32(self.app_info_id)?;
         bytes.write_u32(self.sort_info_id)?;
-        bytes.write(&self.type_.as_bytes()[0..=3])?;
-        bytes.write(&self.creator.as_bytes()[0..=3])?;
+        bytes.write_all(self.type_.as_bytes())?;
+        bytes.write_all(self.creator.as_bytes())?;
         bytes.write_u32(self.unique_id_seed)?;
         bytes.write_u32(self.next_record_list_id)?;
         bytes.write_u16(self.number_of_records)?;
-        assert_eq!(bytes.len(), 78, "PDB header must be exactly 78 bytes long");
 
         Ok(bytes)
     }
+
+    /// Resource databases (`.prc` files) list typed resources instead of
+    /// records, see [`prc::PRC`].
+    pub fn is_resource_db(&self) -> bool {
+        self.attributes.contains(DatabaseAttributes::RESOURCE_DB)
+    }
 }
 
+/// Unique IDs are stored in 3 bytes of the record list entry.
+pub const MAX_UNIQUE_ID: u32 = 0x00FF_FFFF;
+
 #[derive(Debug, Clone)]
 pub struct PDBRecord {
     pub data_offset: u32,
-    pub attributes: u32, // First byte is attributes, next 3 are unique ID
+    pub attributes: RecordAttributes,
+    pub unique_id: u32,
 }
 
 impl PDBRecord {
-    pub fn from_bytes<R: Read>(reader: &mut R) -> std::io::Result<(Self, u32)> {
+    pub fn from_bytes<R: Read>(reader: &mut R) -> Result<(Self, u32), PDBError> {
         let data_offset = reader.read_u32()?;
-        let attributes = reader.read_u32()?;
+        let attributes = RecordAttributes::from_bits_retain(reader.read_u8()?);
+        let mut unique_id = [0u8; 4];
+        reader.read_exact(&mut unique_id[1..])?;
 
         Ok((
             PDBRecord {
                 data_offset,
                 attributes,
+                unique_id: u32::from_be_bytes(unique_id),
             },
             data_offset,
         ))
     }
-    pub fn to_bytes(&self, data_offset: u32) -> std::io::Result<Vec<u8>> {
+    pub fn to_bytes(&self, data_offset: u32) -> Result<Vec<u8>, PDBError> {
+        if self.unique_id > MAX_UNIQUE_ID {
+            return Err(PDBError::UniqueIdOverflow(self.unique_id));
+        }
+
         let mut bytes = Vec::new();
         bytes.write_u32(data_offset)?;
-        bytes.write_u32(self.attributes)?;
+        bytes.write_u8(self.attributes.bits())?;
+        bytes.write_all(&self.unique_id.to_be_bytes()[1..])?;
         Ok(bytes)
     }
 }
 
+/// Reads the record list that follows `header`.
+pub(crate) fn read_record_list<R: Read>(
+    reader: &mut R,
+    header: &PDBHeader,
+    limits: &ParseLimits,
+) -> Result<Vec<PDBRecord>, PDBError> {
+    if header.is_resource_db() {
+        return Err(PDBError::ResourceDatabase);
+    }
+    limits.check_records(header.number_of_records)?;
+
+    let mut records = Vec::with_capacity(header.number_of_records as usize);
+    for _ in 0..header.number_of_records {
+        records.push(PDBRecord::from_bytes(reader)?.0);
+    }
+    Ok(records)
+}
+
+/// A record together with its entry in the record list.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    pub attributes: RecordAttributes,
+    pub unique_id: u32,
+    pub data: Vec<u8>,
+}
+
+impl Record {
+    fn entry(&self, data_offset: u32) -> PDBRecord {
+        PDBRecord {
+            data_offset,
+            attributes: self.attributes,
+            unique_id: self.unique_id,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct PDB {
     pub header: PDBHeader,
-    pub records: Vec<PDBRecord>,
-    pub record_data: Vec<Vec<u8>>,
+    /// Application specific data, such as a category table. `app_info_id` in
+    /// the header is filled in when the database is written.
+    pub app_info: Option<Vec<u8>>,
+    /// Application specific sorting data. `sort_info_id` in the header is
+    /// filled in when the database is written.
+    pub sort_info: Option<Vec<u8>>,
+    records: Vec<Record>,
 }
 
 impl PDB {
     pub fn new(header: PDBHeader) -> Self {
         Self {
             header,
+            app_info: None,
+            sort_info: None,
             records: vec![],
-            record_data: vec![],
         }
     }
 
+    pub fn from_records(mut header: PDBHeader, records: Vec<Record>) -> Self {
+        header.number_of_records = records.len() as u16;
+        Self {
+            header,
+            app_info: None,
+            sort_info: None,
+            records,
+        }
+    }
+
+    pub fn records(&self) -> &[Record] {
+        &self.records
+    }
+
+    pub fn record(&self, index: u16) -> Option<&Record> {
+        self.records.get(index as usize)
+    }
+
     pub fn read_record(&self, index: u16) -> Option<Vec<u8>> {
-        self.record_data.get(index as usize).cloned()
+        self.record(index).map(|record| record.data.clone())
     }
 
-    pub fn add_record(&mut self, data: Vec<u8>) -> u16 {
-        let id = self.header.unique_id_seed;
-        let data_offset = self
+    /// Hands out the next unused unique ID, skipping any already taken by
+    /// records that were read from a file.
+    fn next_unique_id(&mut self) -> u32 {
+        let taken = self
             .records
-            .last()
-            .unwrap_or(&PDBRecord {
-                data_offset: 0,
-                attributes: 0,
-            })
-            .data_offset
-            + self.record_data.last().unwrap_or(&vec![]).len() as u32;
-        self.records.push(PDBRecord {
-            data_offset,
-            attributes: id,
-        });
-        self.record_data.push(data);
-        self.header.unique_id_seed += 2;
-        self.header.number_of_records += 1;
+            .iter()
+            .map(|record| record.unique_id + 1)
+            .max()
+            .unwrap_or(0);
+        let id = self.header.unique_id_seed.max(taken);
+        self.header.unique_id_seed = id + 2;
+        id
+    }
 
+    fn check_index(&self, index: u16, len: usize) -> Result<usize, PDBError> {
+        if (index as usize) < len {
+            Ok(index as usize)
+        } else {
+            Err(PDBError::RecordOutOfRange { index, count: len })
+        }
+    }
+
+    pub fn add_record(&mut self, data: Vec<u8>) -> u16 {
+        let unique_id = self.next_unique_id();
+        self.records.push(Record {
+            attributes: RecordAttributes::empty(),
+            unique_id,
+            data,
+        });
+        self.header.number_of_records = self.records.len() as u16;
 
         self.header.number_of_records - 1
     }
+
+    /// Inserts a record before `index`, shifting later records up. Returns the
+    /// unique ID given to the new record.
+    pub fn insert_record(&mut self, index: u16, data: Vec<u8>) -> Result<u32, PDBError> {
+        let index = self.check_index(index, self.records.len() + 1)?;
+        let unique_id = self.next_unique_id();
+        self.records.insert(
+            index,
+            Record {
+                attributes: RecordAttributes::empty(),
+                unique_id,
+                data,
+            },
+        );
+        self.header.number_of_records = self.records.len() as u16;
+
+        Ok(unique_id)
+    }
+
+    pub fn remove_record(&mut self, index: u16) -> Result<Record, PDBError> {
+        let index = self.check_index(index, self.records.len())?;
+        let record = self.records.remove(index);
+        self.header.number_of_records = self.records.len() as u16;
+
+        Ok(record)
+    }
+
+    /// Replaces the data of a record, keeping its attributes and unique ID.
+    /// Returns the previous data.
+    pub fn replace_record(&mut self, index: u16, data: Vec<u8>) -> Result<Vec<u8>, PDBError> {
+        let index = self.check_index(index, self.records.len())?;
+        Ok(std::mem::replace(&mut self.records[index].data, data))
+    }
+
+    pub fn swap_records(&mut self, a: u16, b: u16) -> Result<(), PDBError> {
+        let a = self.check_index(a, self.records.len())?;
+        let b = self.check_index(b, self.records.len())?;
+        self.records.swap(a, b);
+        Ok(())
+    }
+}
+
+/// Computes the byte range of each record from the record list, where a record
+/// runs until the next one starts and the last one until the end of the file.
+pub fn record_ranges(
+    records: &[PDBRecord],
+    file_len: u64,
+    limits: &ParseLimits,
+) -> Result<Vec<Range<u64>>, PDBError> {
+    let offsets: Vec<u32> = records.iter().map(|record| record.data_offset).collect();
+    data_ranges(&offsets, file_len, limits)
+}
+
+/// Like [`record_ranges`], but for any list of data offsets, such as the
+/// entries of a resource database.
+pub(crate) fn data_ranges(
+    offsets: &[u32],
+    file_len: u64,
+    limits: &ParseLimits,
+) -> Result<Vec<Range<u64>>, PDBError> {
+    let mut ranges = Vec::with_capacity(offsets.len());
+
+    for (i, &offset) in offsets.iter().enumerate() {
+        let start = offset as u64;
+        let end = offsets
+            .get(i + 1)
+            .map(|&next| next as u64)
+            .unwrap_or(file_len);
+
+        if start > file_len {
+            return Err(PDBError::OffsetOutOfRange {
+                record: i as u16,
+                offset,
+                file_len,
+            });
+        }
+        if start > end {
+            return Err(PDBError::OffsetsOutOfOrder {
+                record: i as u16 + 1,
+            });
+        }
+        limits.check_record_size(end - start)?;
+        ranges.push(start..end);
+    }
+
+    Ok(ranges)
+}
+
+/// Size of the placeholder conventionally written between the record list and
+/// the first data block.
+pub const RECORD_LIST_PADDING: u32 = 2;
+
+/// Finds the extent of the AppInfo or SortInfo block at `start`, which runs
+/// until the next block that follows it.
+fn block_range(
+    start: u32,
+    boundaries: &[u64],
+    file_len: u64,
+) -> Result<Option<Range<u64>>, PDBError> {
+    if start == 0 {
+        return Ok(None);
+    }
+
+    let start = start as u64;
+    if start > file_len {
+        return Err(PDBError::InvalidData(format!(
+            "AppInfo or SortInfo offset {start} is past the end of the file ({file_len} bytes)"
+        )));
+    }
+    let end = boundaries
+        .iter()
+        .copied()
+        .filter(|&boundary| boundary > start)
+        .min()
+        .unwrap_or(file_len)
+        .min(file_len);
+
+    Ok(Some(start..end))
+}
+
+/// The AppInfo and SortInfo blocks of a database.
+pub(crate) type InfoBlocks = (Option<Vec<u8>>, Option<Vec<u8>>);
+
+/// Reads the AppInfo and SortInfo blocks, which sit between the record list
+/// and the first record.
+pub(crate) fn read_info_blocks<R: Read + Seek>(
+    reader: &mut R,
+    header: &PDBHeader,
+    first_record: u64,
+    file_len: u64,
+    limits: &ParseLimits,
+) -> Result<InfoBlocks, PDBError> {
+    let boundaries = [
+        header.app_info_id as u64,
+        header.sort_info_id as u64,
+        first_record,
+    ];
+    let mut read_block = |start| {
+        block_range(start, &boundaries, file_len)?
+            .map(|range| {
+                limits.check_record_size(range.end - range.start)?;
+                read_range(reader, range)
+            })
+            .transpose()
+    };
+    let app_info = read_block(header.app_info_id)?;
+    let sort_info = read_block(header.sort_info_id)?;
+
+    Ok((app_info, sort_info))
+}
+
+/// Checks that an offset into the file fits in the 32 bits the headers store
+/// it in, which limits databases to 4 GiB.
+pub(crate) fn file_offset(offset: u64) -> Result<u32, PDBError> {
+    u32::try_from(offset).map_err(|_| PDBError::LimitExceeded {
+        what: "database size",
+        value: offset,
+        limit: u32::MAX as u64,
+    })
+}
+
+/// The offset just past `len` bytes written at `offset`.
+pub(crate) fn advance_offset(offset: u32, len: usize) -> Result<u32, PDBError> {
+    file_offset(offset as u64 + len as u64)
+}
+
+/// Assigns offsets to the AppInfo and SortInfo blocks, which are written
+/// starting at `offset`, and returns the offset just past them.
+pub(crate) fn place_info_blocks(
+    header: &mut PDBHeader,
+    mut offset: u32,
+    app_info: &Option<Vec<u8>>,
+    sort_info: &Option<Vec<u8>>,
+) -> Result<u32, PDBError> {
+    let mut place_block = |block: &Option<Vec<u8>>| -> Result<u32, PDBError> {
+        let Some(data) = block else {
+            return Ok(0);
+        };
+        let block_offset = offset;
+        offset = advance_offset(offset, data.len())?;
+        Ok(block_offset)
+    };
+    header.app_info_id = place_block(app_info)?;
+    header.sort_info_id = place_block(sort_info)?;
+    Ok(offset)
+}
+
+pub(crate) fn read_range<R: Read + Seek>(
+    reader: &mut R,
+    range: Range<u64>,
+) -> Result<Vec<u8>, PDBError> {
+    reader.seek(SeekFrom::Start(range.start))?;
+    let mut data = vec![0u8; (range.end - range.start) as usize];
+    reader.read_exact(&mut data)?;
+    Ok(data)
+}
+
+/// Read access to the records of a database, whether they are held in memory
+/// or fetched on demand.
+pub trait RecordSource {
+    fn header(&self) -> &PDBHeader;
+    fn record_data(&self, index: u16) -> Result<Cow<'_, [u8]>, PDBError>;
+}
+
+impl RecordSource for PDB {
+    fn header(&self) -> &PDBHeader {
+        &self.header
+    }
+
+    fn record_data(&self, index: u16) -> Result<Cow<'_, [u8]>, PDBError> {
+        self.record(index)
+            .map(|record| Cow::Borrowed(record.data.as_slice()))
+            .ok_or(PDBError::RecordOutOfRange {
+                index,
+                count: self.records.len(),
+            })
+    }
 }
 
 impl PDB {
-    pub fn from_bytes<R: Read + Seek>(reader: &mut R) -> std::io::Result<Self> {
+    /// Reads a database with the default [`ParseLimits`].
+    pub fn from_bytes<R: Read + Seek>(reader: &mut R) -> Result<Self, PDBError> {
+        Self::from_bytes_with_limits(reader, &ParseLimits::default())
+    }
+
+    pub fn from_bytes_with_limits<R: Read + Seek>(
+        reader: &mut R,
+        limits: &ParseLimits,
+    ) -> Result<Self, PDBError> {
         let header = PDBHeader::from_bytes(reader)?;
-        let mut records = Vec::new();
-        let mut record_data = Vec::new();
+        let entries = read_record_list(reader, &header, limits)?;
+        let mut records = Vec::with_capacity(entries.len());
 
-        for _ in 0..header.number_of_records {
-            records.push(PDBRecord::from_bytes(reader)?);
+        let file_len = reader.seek(SeekFrom::End(0))?;
+        for (entry, range) in entries
+            .iter()
+            .zip(record_ranges(&entries, file_len, limits)?)
+        {
+            records.push(Record {
+                attributes: entry.attributes,
+                unique_id: entry.unique_id,
+                data: read_range(reader, range)?,
+            });
         }
 
-        let record_count = records.len();
-
-        for (i, (_, data_offset)) in records.iter().cloned().enumerate() {
-            let start = data_offset as u64;
-
-            let end = if i + 1 < record_count {
-                records[i + 1].1 as u64
-            } else {
-                let end = reader.seek(SeekFrom::End(0))?;
-                if end < start {
-                    return Err(Error::new(
-                        ErrorKind::InvalidData,
-                        "Invalid record data offset",
-                    ));
-                }
-                end
-            };
-
-            let len = (end - start) as usize;
-            reader.seek(SeekFrom::Start(start))?;
-            let mut data = vec![0u8; len];
-            reader.read_exact(&mut data)?;
-            record_data.push(data);
-        }
+        let first_record = entries
+            .first()
+            .map(|entry| entry.data_offset as u64)
+            .unwrap_or(file_len);
+        let (app_info, sort_info) =
+            read_info_blocks(reader, &header, first_record, file_len, limits)?;
 
         Ok(PDB {
             header,
-            records: records.iter().map(|(record, _)| record).cloned().collect(),
-            record_data,
+            app_info,
+            sort_info,
+            records,
         })
     }
 
-    pub fn to_bytes(&self) -> std::io::Result<Vec<u8>> {
-        let mut bytes = self.header.to_bytes()?;
-        let mut offset: u32 = 78 + self.records.len() as u32 * 8; // Header and records size
-        for (i, record) in self.records.iter().enumerate() {
-            let data_offset = offset;
-            let data = self
-                .record_data
-                .get(i)
-                .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Missing record data"))?;
-            offset += data.len() as u32;
-            bytes.extend_from_slice(&record.to_bytes(data_offset)?);
+    pub fn to_bytes(&self) -> Result<Vec<u8>, PDBError> {
+        let mut bytes = Vec::new();
+        self.write_to(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<(), PDBError> {
+        let mut header = self.header.clone();
+        header.number_of_records = self.records.len() as u16;
+
+        // Header and records size
+        let mut offset = place_info_blocks(
+            &mut header,
+            78 + self.records.len() as u32 * 8 + RECORD_LIST_PADDING,
+            &self.app_info,
+            &self.sort_info,
+        )?;
+
+        writer.write_all(&header.to_bytes()?)?;
+        for record in &self.records {
+            writer.write_all(&record.entry(offset).to_bytes(offset)?)?;
+            offset = advance_offset(offset, record.data.len())?;
         }
-        for data in &self.record_data {
-            bytes.extend_from_slice(data);
+        writer.write_all(&[0; RECORD_LIST_PADDING as usize])?;
+        for block in [&self.app_info, &self.sort_info].into_iter().flatten() {
+            writer.write_all(block)?;
         }
-        Ok(bytes)
+        for record in &self.records {
+            writer.write_all(&record.data)?;
+        }
+        Ok(())
     }
 }
 
@@ -228,17 +589,17 @@ impl PDB {
 mod tests {
     use super::*;
     use crate::builder::PDBBuilder;
-    use std::fs::File;
 
     #[test]
     fn test_pdb_header_to_bytes() {
         let header = PDBHeader {
             name: "TestDB".to_owned(),
-            attributes: 0,
+            name_encoding: TextEncoding::Utf8,
+            attributes: DatabaseAttributes::empty(),
             version: 1,
-            creation_time: chrono::DateTime::from_timestamp(0, 0).unwrap().naive_utc(),
-            modification_time: chrono::DateTime::from_timestamp(0, 0).unwrap().naive_utc(),
-            last_backup_date: chrono::DateTime::from_timestamp(0, 0).unwrap().naive_utc(),
+            creation_time: None,
+            modification_time: None,
+            last_backup_date: None,
             modification_number: 0,
             app_info_id: 0,
             sort_info_id: 0,
@@ -252,13 +613,25 @@ mod tests {
             .to_bytes()
             .expect("Failed to convert header to bytes");
         assert_eq!(bytes.len(), 78, "PDB header must be exactly 78 bytes long");
+
+        let mut cp1252 = header.clone();
+        cp1252.name = "Café".to_owned();
+        cp1252.name_encoding = TextEncoding::Cp1252;
+        let bytes = cp1252.to_bytes().unwrap();
+        assert_eq!(&bytes[..5], b"Caf\xe9\0");
+        let read = PDBHeader::from_bytes(&mut &bytes[..]).unwrap();
+        assert_eq!(read.name, "Café");
+        assert_eq!(read.name_encoding, TextEncoding::Cp1252);
+
+        cp1252.name = "日本".to_owned();
+        assert!(cp1252.to_bytes().is_err());
     }
 
     #[test]
     fn test_pdb_to_bytes() {
         let pdb = PDBBuilder::new()
             .name("TestDB".to_owned())
-            .attributes(0)
+            .attributes(DatabaseAttributes::BACKUP)
             .version(1)
             .type_("TEST".to_owned())
             .creator("TEST".to_owned())
@@ -268,4 +641,98 @@ mod tests {
             .expect("Failed to build PDB");
         _ = pdb.to_bytes().expect("Failed to convert PDB to bytes");
     }
+
+    #[test]
+    fn test_record_editing() {
+        let mut pdb = PDBBuilder::new()
+            .name("TestDB")
+            .type_("TEST")
+            .creator("TEST")
+            .add_record(0, 0, b"a")
+            .add_record(2, 0, b"b")
+            .build()
+            .expect("Failed to build PDB");
+
+        let c = pdb.insert_record(1, b"c".to_vec()).unwrap();
+        assert_eq!(c, 3);
+        assert_eq!(pdb.header.number_of_records, 3);
+
+        assert_eq!(pdb.replace_record(2, b"B".to_vec()).unwrap(), b"b");
+        assert_eq!(pdb.record(2).unwrap().unique_id, 2);
+
+        pdb.swap_records(0, 2).unwrap();
+        let removed = pdb.remove_record(1).unwrap();
+        assert_eq!(removed.data, b"c");
+        assert_eq!(pdb.header.number_of_records, 2);
+
+        assert!(pdb.remove_record(2).is_err());
+        assert!(pdb.insert_record(3, vec![]).is_err());
+
+        let bytes = pdb.to_bytes().expect("Failed to convert PDB to bytes");
+        let pdb = PDB::from_bytes(&mut std::io::Cursor::new(bytes)).expect("Failed to read PDB");
+        assert_eq!(
+            pdb.records(),
+            &[
+                Record {
+                    attributes: RecordAttributes::empty(),
+                    unique_id: 2,
+                    data: b"B".to_vec(),
+                },
+                Record {
+                    attributes: RecordAttributes::empty(),
+                    unique_id: 0,
+                    data: b"a".to_vec(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_record_entry_round_trip() {
+        let entry = PDBRecord {
+            data_offset: 0x1234,
+            attributes: RecordAttributes::SECRET.with_category(3),
+            unique_id: 0x00AB_CDEF,
+        };
+        let bytes = entry.to_bytes(entry.data_offset).unwrap();
+        assert_eq!(bytes, [0x00, 0x00, 0x12, 0x34, 0x13, 0xAB, 0xCD, 0xEF]);
+
+        let (read, _) = PDBRecord::from_bytes(&mut bytes.as_slice()).unwrap();
+        assert_eq!(read.attributes, entry.attributes);
+        assert_eq!(read.unique_id, entry.unique_id);
+
+        let overflow = PDBRecord {
+            unique_id: MAX_UNIQUE_ID + 1,
+            ..entry
+        };
+        assert!(overflow.to_bytes(0).is_err());
+    }
+
+    #[test]
+    fn test_app_info_round_trip() {
+        let mut pdb = PDBBuilder::new()
+            .name("TestDB")
+            .type_("DATA")
+            .creator("TEST")
+            .add_record(0, 0, b"record")
+            .build()
+            .expect("Failed to build PDB");
+        pdb.app_info = Some(b"categories".to_vec());
+        pdb.sort_info = Some(b"sort".to_vec());
+
+        let bytes = pdb.to_bytes().expect("Failed to convert PDB to bytes");
+        let read = PDB::from_bytes(&mut std::io::Cursor::new(&bytes)).expect("Failed to read PDB");
+        assert_eq!(read.header.app_info_id, 78 + 8 + 2);
+        assert_eq!(read.header.sort_info_id, 78 + 8 + 2 + 10);
+        assert_eq!(read.app_info.as_deref(), Some(&b"categories"[..]));
+        assert_eq!(read.sort_info.as_deref(), Some(&b"sort"[..]));
+        assert_eq!(read.read_record(0).unwrap(), b"record");
+        assert_eq!(read.to_bytes().unwrap(), bytes);
+
+        pdb.sort_info = None;
+        let bytes = pdb.to_bytes().expect("Failed to convert PDB to bytes");
+        let read = PDB::from_bytes(&mut std::io::Cursor::new(&bytes)).expect("Failed to read PDB");
+        assert_eq!(read.header.sort_info_id, 0);
+        assert_eq!(read.app_info.as_deref(), Some(&b"categories"[..]));
+    }
 }
@@ -0,0 +1,100 @@
+use crate::PDBError;
+
+/// Bounds on what a parser will accept from a file, so that a malicious upload
+/// can't make it allocate or read without limit.
+///
+/// Record offsets are always checked against the file length; these limits
+/// are applied on top of that.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseLimits {
+    /// Maximum number of entries in the record or resource list.
+    pub max_records: u16,
+    /// Maximum size of a single record, resource, AppInfo or SortInfo block.
+    pub max_record_size: u64,
+}
+
+impl Default for ParseLimits {
+    fn default() -> Self {
+        Self {
+            max_records: u16::MAX,
+            max_record_size: 16 * 1024 * 1024,
+        }
+    }
+}
+
+impl ParseLimits {
+    /// No limits beyond what the format itself allows.
+    pub fn unlimited() -> Self {
+        Self {
+            max_records: u16::MAX,
+            max_record_size: u64::MAX,
+        }
+    }
+
+    pub(crate) fn check_records(&self, count: u16) -> Result<(), PDBError> {
+        check("records", count as u64, self.max_records as u64)
+    }
+
+    pub(crate) fn check_record_size(&self, size: u64) -> Result<(), PDBError> {
+        check("record size", size, self.max_record_size)
+    }
+}
+
+fn check(what: &'static str, value: u64, limit: u64) -> Result<(), PDBError> {
+    if value > limit {
+        Err(PDBError::LimitExceeded { what, value, limit })
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PDB;
+    use crate::builder::PDBBuilder;
+    use crate::pdb_ref::PdbRef;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_parse_limits() {
+        let pdb = PDBBuilder::new()
+            .name("TestDB")
+            .type_("DATA")
+            .creator("TEST")
+            .add_record(0, 0, &[0; 100])
+            .add_record(2, 0, b"small")
+            .add_record(4, 0, b"small")
+            .build()
+            .expect("Failed to build PDB");
+        let bytes = pdb.to_bytes().unwrap();
+
+        let few_records = ParseLimits {
+            max_records: 2,
+            ..ParseLimits::default()
+        };
+        assert!(matches!(
+            PDB::from_bytes_with_limits(&mut Cursor::new(&bytes), &few_records),
+            Err(PDBError::LimitExceeded {
+                what: "records",
+                value: 3,
+                limit: 2
+            })
+        ));
+
+        let small_records = ParseLimits {
+            max_record_size: 50,
+            ..ParseLimits::default()
+        };
+        assert!(matches!(
+            PdbRef::with_limits(&bytes, &small_records),
+            Err(PDBError::LimitExceeded {
+                what: "record size",
+                value: 100,
+                limit: 50
+            })
+        ));
+
+        assert!(PDB::from_bytes(&mut Cursor::new(&bytes)).is_ok());
+    }
+}
@@ -0,0 +1,121 @@
+use crate::{
+    PDBError, PDBHeader, PDBRecord, ParseLimits, RecordSource, read_record_list, record_ranges,
+};
+use std::borrow::Cow;
+use std::io::Cursor;
+use std::ops::Range;
+
+/// A database parsed in place from a byte slice, e.g. a memory mapped file or
+/// an upload held in memory. Records are returned as sub-slices of the input
+/// instead of being copied.
+#[derive(Debug, Clone)]
+pub struct PdbRef<'a> {
+    pub header: PDBHeader,
+    pub records: Vec<PDBRecord>,
+    ranges: Vec<Range<usize>>,
+    data: &'a [u8],
+}
+
+impl<'a> PdbRef<'a> {
+    /// Parses the header and record list with the default [`ParseLimits`].
+    pub fn new(data: &'a [u8]) -> Result<Self, PDBError> {
+        Self::with_limits(data, &ParseLimits::default())
+    }
+
+    pub fn with_limits(data: &'a [u8], limits: &ParseLimits) -> Result<Self, PDBError> {
+        let mut reader = Cursor::new(data);
+        let header = PDBHeader::from_bytes(&mut reader)?;
+        let records = read_record_list(&mut reader, &header, limits)?;
+
+        let ranges = record_ranges(&records, data.len() as u64, limits)?
+            .into_iter()
+            .map(|range| range.start as usize..range.end as usize)
+            .collect();
+
+        Ok(Self {
+            header,
+            records,
+            ranges,
+            data,
+        })
+    }
+
+    pub fn read_record(&self, index: u16) -> Option<&'a [u8]> {
+        let data = self.data;
+        self.ranges
+            .get(index as usize)
+            .map(|range| &data[range.clone()])
+    }
+
+    pub fn records(&self) -> impl Iterator<Item = &'a [u8]> + '_ {
+        self.ranges.iter().map(|range| &self.data[range.clone()])
+    }
+}
+
+impl RecordSource for PdbRef<'_> {
+    fn header(&self) -> &PDBHeader {
+        &self.header
+    }
+
+    fn record_data(&self, index: u16) -> Result<Cow<'_, [u8]>, PDBError> {
+        self.read_record(index)
+            .map(Cow::Borrowed)
+            .ok_or(PDBError::RecordOutOfRange {
+                index,
+                count: self.ranges.len(),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::PDBBuilder;
+
+    #[test]
+    fn test_pdb_ref() {
+        let mut pdb = PDBBuilder::new()
+            .name("TestDB")
+            .type_("TEST")
+            .creator("TEST")
+            .build()
+            .expect("Failed to build PDB");
+        pdb.add_record(b"Record 1 data".to_vec());
+        pdb.add_record(b"Record 2".to_vec());
+        let bytes = pdb.to_bytes().expect("Failed to convert PDB to bytes");
+
+        let pdb_ref = PdbRef::new(&bytes).expect("Failed to read PDB");
+        assert_eq!(pdb_ref.header.name, "TestDB");
+        assert_eq!(pdb_ref.read_record(0), Some(&b"Record 1 data"[..]));
+        assert_eq!(pdb_ref.read_record(1), Some(&b"Record 2"[..]));
+        assert_eq!(pdb_ref.read_record(2), None);
+        assert_eq!(pdb_ref.records().count(), 2);
+    }
+
+    #[test]
+    fn test_pdb_ref_rejects_bad_offsets() {
+        let mut pdb = PDBBuilder::new()
+            .name("TestDB")
+            .type_("TEST")
+            .creator("TEST")
+            .build()
+            .expect("Failed to build PDB");
+        pdb.add_record(b"Record 1 data".to_vec());
+        let mut bytes = pdb.to_bytes().expect("Failed to convert PDB to bytes");
+
+        // Point the only record past the end of the file.
+        bytes[78..82].copy_from_slice(&0xFFFFu32.to_be_bytes());
+        assert!(matches!(
+            PdbRef::new(&bytes),
+            Err(PDBError::OffsetOutOfRange {
+                record: 0,
+                offset: 0xFFFF,
+                ..
+            })
+        ));
+        assert!(matches!(
+            PdbRef::new(&bytes[..40]),
+            Err(PDBError::Truncated)
+        ));
+    }
+}
@@ -0,0 +1,249 @@
+use crate::{
+    DatabaseAttributes, PDBError, PDBHeader, ParseLimits, RECORD_LIST_PADDING, advance_offset,
+    data_ranges, place_info_blocks, read_info_blocks, read_range,
+};
+use byyte::be::{ByteReader, ByteWriter};
+use std::io::{Read, Seek, SeekFrom, Write};
+
+/// Size of an entry in the resource list: type, ID and data offset.
+pub const RESOURCE_ENTRY_SIZE: u32 = 10;
+
+/// An entry in the resource list of a resource database.
+#[derive(Debug, Clone)]
+pub struct PRCResource {
+    pub type_: String,
+    pub id: u16,
+    pub data_offset: u32,
+}
+
+impl PRCResource {
+    pub fn from_bytes<R: Read>(reader: &mut R) -> Result<Self, PDBError> {
+        let mut type_ = [0u8; 4];
+        reader.read_exact(&mut type_)?;
+        let id = reader.read_u16()?;
+        let data_offset = reader.read_u32()?;
+
+        Ok(PRCResource {
+            type_: String::from_utf8_lossy(&type_).into_owned(),
+            id,
+            data_offset,
+        })
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, PDBError> {
+        if self.type_.len() != 4 {
+            return Err(PDBError::InvalidData(format!(
+                "Resource type {:?} must be exactly 4 bytes",
+                self.type_
+            )));
+        }
+
+        let mut bytes = Vec::new();
+        bytes.write_all(self.type_.as_bytes())?;
+        bytes.write_u16(self.id)?;
+        bytes.write_u32(self.data_offset)?;
+        Ok(bytes)
+    }
+}
+
+/// A resource together with its entry in the resource list, e.g. the `code`
+/// resource with ID 1 of an application.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Resource {
+    pub type_: String,
+    pub id: u16,
+    pub data: Vec<u8>,
+}
+
+/// A resource database, as used by `.prc` applications. The header is the
+/// same as for record databases, but has the resource attribute set and is
+/// followed by a list of typed resources instead of records.
+#[derive(Debug, Clone)]
+pub struct PRC {
+    pub header: PDBHeader,
+    pub app_info: Option<Vec<u8>>,
+    pub sort_info: Option<Vec<u8>>,
+    pub resources: Vec<Resource>,
+}
+
+impl PRC {
+    pub fn new(mut header: PDBHeader) -> Self {
+        header.attributes |= DatabaseAttributes::RESOURCE_DB;
+        Self {
+            header,
+            app_info: None,
+            sort_info: None,
+            resources: vec![],
+        }
+    }
+
+    pub fn resource(&self, type_: &str, id: u16) -> Option<&Resource> {
+        self.resources
+            .iter()
+            .find(|resource| resource.type_ == type_ && resource.id == id)
+    }
+
+    pub fn add_resource(&mut self, type_: impl Into<String>, id: u16, data: Vec<u8>) {
+        self.resources.push(Resource {
+            type_: type_.into(),
+            id,
+            data,
+        });
+        self.header.number_of_records = self.resources.len() as u16;
+    }
+
+    /// Reads a resource database with the default [`ParseLimits`].
+    pub fn from_bytes<R: Read + Seek>(reader: &mut R) -> Result<Self, PDBError> {
+        Self::from_bytes_with_limits(reader, &ParseLimits::default())
+    }
+
+    pub fn from_bytes_with_limits<R: Read + Seek>(
+        reader: &mut R,
+        limits: &ParseLimits,
+    ) -> Result<Self, PDBError> {
+        let header = PDBHeader::from_bytes(reader)?;
+        if !header.is_resource_db() {
+            return Err(PDBError::NotResourceDatabase);
+        }
+        limits.check_records(header.number_of_records)?;
+
+        let mut entries = Vec::with_capacity(header.number_of_records as usize);
+        for _ in 0..header.number_of_records {
+            entries.push(PRCResource::from_bytes(reader)?);
+        }
+
+        let file_len = reader.seek(SeekFrom::End(0))?;
+        let offsets: Vec<u32> = entries.iter().map(|entry| entry.data_offset).collect();
+        let mut resources = Vec::with_capacity(entries.len());
+        for (entry, range) in entries
+            .into_iter()
+            .zip(data_ranges(&offsets, file_len, limits)?)
+        {
+            resources.push(Resource {
+                type_: entry.type_,
+                id: entry.id,
+                data: read_range(reader, range)?,
+            });
+        }
+
+        let first_resource = offsets.first().map_or(file_len, |&offset| offset as u64);
+        let (app_info, sort_info) =
+            read_info_blocks(reader, &header, first_resource, file_len, limits)?;
+
+        Ok(PRC {
+            header,
+            app_info,
+            sort_info,
+            resources,
+        })
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, PDBError> {
+        let mut bytes = Vec::new();
+        self.write_to(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<(), PDBError> {
+        let mut header = self.header.clone();
+        header.attributes |= DatabaseAttributes::RESOURCE_DB;
+        header.number_of_records = self.resources.len() as u16;
+
+        let mut offset = place_info_blocks(
+            &mut header,
+            78 + self.resources.len() as u32 * RESOURCE_ENTRY_SIZE + RECORD_LIST_PADDING,
+            &self.app_info,
+            &self.sort_info,
+        )?;
+
+        writer.write_all(&header.to_bytes()?)?;
+        for resource in &self.resources {
+            let entry = PRCResource {
+                type_: resource.type_.clone(),
+                id: resource.id,
+                data_offset: offset,
+            };
+            writer.write_all(&entry.to_bytes()?)?;
+            offset = advance_offset(offset, resource.data.len())?;
+        }
+        writer.write_all(&[0; RECORD_LIST_PADDING as usize])?;
+        for block in [&self.app_info, &self.sort_info].into_iter().flatten() {
+            writer.write_all(block)?;
+        }
+        for resource in &self.resources {
+            writer.write_all(&resource.data)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PDB;
+    use crate::builder::PDBBuilder;
+    use std::io::Cursor;
+
+    fn test_prc() -> PRC {
+        let pdb = PDBBuilder::new()
+            .name("TestApp")
+            .type_("appl")
+            .creator("TEST")
+            .build()
+            .expect("Failed to build PDB");
+        PRC::new(pdb.header)
+    }
+
+    #[test]
+    fn test_prc_round_trip() {
+        let mut prc = test_prc();
+        prc.add_resource("code", 0, b"globals".to_vec());
+        prc.add_resource("code", 1, b"main".to_vec());
+        prc.add_resource("tAIN", 1000, b"Test App\0".to_vec());
+        prc.app_info = Some(b"info".to_vec());
+
+        let bytes = prc.to_bytes().expect("Failed to convert PRC to bytes");
+        assert_eq!(&bytes[78..82], b"code");
+        assert_eq!(&bytes[78 + 30 + 2..78 + 30 + 2 + 4], b"info");
+
+        let read = PRC::from_bytes(&mut Cursor::new(&bytes)).expect("Failed to read PRC");
+        assert!(read.header.is_resource_db());
+        assert_eq!(read.resources, prc.resources);
+        assert_eq!(read.resource("code", 1).unwrap().data, b"main");
+        assert_eq!(read.resource("tAIN", 1000).unwrap().data, b"Test App\0");
+        assert!(read.resource("code", 2).is_none());
+        assert_eq!(read.app_info.as_deref(), Some(&b"info"[..]));
+        assert_eq!(read.to_bytes().unwrap(), bytes);
+    }
+
+    #[test]
+    fn test_database_kind_mismatch() {
+        let mut prc = test_prc();
+        prc.add_resource("code", 1, b"main".to_vec());
+        let bytes = prc.to_bytes().unwrap();
+        assert!(matches!(
+            PDB::from_bytes(&mut Cursor::new(&bytes)),
+            Err(PDBError::ResourceDatabase)
+        ));
+
+        let pdb = PDBBuilder::new()
+            .name("TestDB")
+            .type_("DATA")
+            .creator("TEST")
+            .add_record(0, 0, b"record")
+            .build()
+            .unwrap();
+        let bytes = pdb.to_bytes().unwrap();
+        assert!(matches!(
+            PRC::from_bytes(&mut Cursor::new(&bytes)),
+            Err(PDBError::NotResourceDatabase)
+        ));
+    }
+
+    #[test]
+    fn test_invalid_resource_type() {
+        let mut prc = test_prc();
+        prc.add_resource("cod", 1, vec![]);
+        assert!(prc.to_bytes().is_err());
+    }
+}
@@ -1,20 +1,113 @@
-use std::io::{Error, ErrorKind};
-
-pub fn from_palm_timestamp(timestamp: u32) -> Result<chrono::NaiveDateTime, Error> {
-    let seconds = timestamp as i64;
-    let epoch = chrono::NaiveDate::from_ymd_opt(1904, 1, 1)
-        .and_then(|t| t.and_hms_opt(0, 0, 0))
-        .ok_or(Error::from(ErrorKind::InvalidData))?;
-    Ok(epoch + chrono::Duration::seconds(seconds))
+use crate::PDBError;
+
+/// The epoch a timestamp counts from. Palm OS counts unsigned seconds since
+/// 1904, while many MOBI generators (Calibre among them) write signed seconds
+/// since 1970. The two are told apart by the high bit, which is set for 1904
+/// based dates after 1972 and clear for 1970 based dates before 2038.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Epoch {
+    #[default]
+    Mac1904,
+    Unix1970,
+}
+
+impl Epoch {
+    fn start(self) -> chrono::NaiveDateTime {
+        let year = match self {
+            Epoch::Mac1904 => 1904,
+            Epoch::Unix1970 => 1970,
+        };
+        chrono::NaiveDate::from_ymd_opt(year, 1, 1)
+            .and_then(|t| t.and_hms_opt(0, 0, 0))
+            .expect("January 1st is a valid date")
+    }
+}
+
+/// A header timestamp together with the epoch it was stored in, so that it is
+/// written back the same way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PalmTimestamp {
+    pub datetime: chrono::NaiveDateTime,
+    pub epoch: Epoch,
+}
+
+impl PalmTimestamp {
+    pub fn new(datetime: chrono::NaiveDateTime, epoch: Epoch) -> Self {
+        Self { datetime, epoch }
+    }
+
+    /// Returns `None` for 0, which marks an unset date.
+    pub fn from_u32(value: u32) -> Option<Self> {
+        if value == 0 {
+            return None;
+        }
+        let epoch = if value & 0x8000_0000 != 0 {
+            Epoch::Mac1904
+        } else {
+            Epoch::Unix1970
+        };
+        Some(Self {
+            datetime: epoch.start() + chrono::Duration::seconds(value as i64),
+            epoch,
+        })
+    }
+
+    /// Fails for dates that would be read back in the other epoch, e.g. 1904
+    /// based dates before 1972.
+    pub fn to_u32(&self) -> Result<u32, PDBError> {
+        let seconds = self
+            .datetime
+            .signed_duration_since(self.epoch.start())
+            .num_seconds();
+        let range = match self.epoch {
+            Epoch::Mac1904 => 0x8000_0000..=u32::MAX as i64,
+            Epoch::Unix1970 => 0..=i32::MAX as i64,
+        };
+        if range.contains(&seconds) {
+            Ok(seconds as u32)
+        } else {
+            Err(PDBError::TimestampOutOfRange(self.datetime))
+        }
+    }
+}
+
+pub fn from_palm_timestamp(timestamp: u32) -> Option<PalmTimestamp> {
+    PalmTimestamp::from_u32(timestamp)
+}
+
+/// Unset dates are written as 0.
+pub fn to_palm_timestamp(timestamp: Option<PalmTimestamp>) -> Result<u32, PDBError> {
+    timestamp.map_or(Ok(0), |timestamp| timestamp.to_u32())
 }
 
-pub fn to_palm_timestamp(datetime: chrono::NaiveDateTime) -> Result<u32, Error> {
-    let epoch = chrono::NaiveDate::from_ymd_opt(1904, 1, 1)
-        .and_then(|t| t.and_hms_opt(0, 0, 0))
-        .ok_or(Error::from(ErrorKind::InvalidData))?;
-    let duration = datetime.signed_duration_since(epoch);
-    if duration.num_seconds() < 0 {
-        return Err(Error::from(ErrorKind::InvalidData));
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_epoch_detection() {
+        assert_eq!(from_palm_timestamp(0), None);
+
+        // 2009-02-13 23:31:30 UTC in both epochs.
+        let datetime = chrono::DateTime::from_timestamp(1_234_567_890, 0)
+            .unwrap()
+            .naive_utc();
+        let unix = from_palm_timestamp(1_234_567_890).unwrap();
+        assert_eq!(unix, PalmTimestamp::new(datetime, Epoch::Unix1970));
+        let mac = from_palm_timestamp(1_234_567_890 + 2_082_844_800).unwrap();
+        assert_eq!(mac, PalmTimestamp::new(datetime, Epoch::Mac1904));
+
+        assert_eq!(to_palm_timestamp(Some(unix)).unwrap(), 1_234_567_890);
+        assert_eq!(
+            to_palm_timestamp(Some(mac)).unwrap(),
+            1_234_567_890 + 2_082_844_800
+        );
+        assert_eq!(to_palm_timestamp(None).unwrap(), 0);
+
+        let ambiguous = PalmTimestamp::new(Epoch::Unix1970.start(), Epoch::Mac1904);
+        assert!(matches!(
+            ambiguous.to_u32(),
+            Err(PDBError::TimestampOutOfRange(_))
+        ));
     }
-    Ok(duration.num_seconds() as u32)
-}
\ No newline at end of file
+}
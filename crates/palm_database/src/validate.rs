@@ -0,0 +1,235 @@
+use crate::prc::RESOURCE_ENTRY_SIZE;
+use crate::{PDBHeader, RECORD_LIST_PADDING};
+use std::collections::HashSet;
+use std::fmt::{Display, Formatter};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// Readers are likely to reject the file or misread it.
+    Error,
+    /// Unusual, but most readers will cope.
+    Warning,
+}
+
+/// A problem found while validating a file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Finding {
+    pub severity: Severity,
+    /// The record the finding is about, if any.
+    pub record: Option<u16>,
+    pub message: String,
+}
+
+impl Finding {
+    pub fn error(record: Option<u16>, message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Error,
+            record,
+            message: message.into(),
+        }
+    }
+
+    pub fn warning(record: Option<u16>, message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Warning,
+            record,
+            message: message.into(),
+        }
+    }
+}
+
+impl Display for Finding {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        match self.record {
+            Some(record) => write!(f, "{severity}: record {record}: {}", self.message),
+            None => write!(f, "{severity}: {}", self.message),
+        }
+    }
+}
+
+/// Checks the structure of a database without failing on the first problem,
+/// unlike the parsers.
+pub fn validate(data: &[u8]) -> Vec<Finding> {
+    let mut findings = vec![];
+
+    if data.len() < 78 {
+        findings.push(Finding::error(
+            None,
+            format!("File is {} bytes, too short for the header", data.len()),
+        ));
+        return findings;
+    }
+    if !data[..32].contains(&0) {
+        findings.push(Finding::warning(None, "Name is not NUL terminated"));
+    }
+    let header = match PDBHeader::from_bytes(&mut &data[..78]) {
+        Ok(header) => header,
+        Err(err) => {
+            findings.push(Finding::error(None, format!("Unreadable header: {err}")));
+            return findings;
+        }
+    };
+
+    let file_len = data.len() as u64;
+    let entry_size = if header.is_resource_db() {
+        RESOURCE_ENTRY_SIZE as u64
+    } else {
+        8
+    };
+    let list_end = 78 + header.number_of_records as u64 * entry_size;
+    if list_end > file_len {
+        findings.push(Finding::error(
+            None,
+            format!(
+                "number_of_records is {}, but the record list would run past the end of the file",
+                header.number_of_records
+            ),
+        ));
+        return findings;
+    }
+
+    let entries: Vec<&[u8]> = data[78..list_end as usize]
+        .chunks(entry_size as usize)
+        .collect();
+    // Record entries start with the offset, resource entries end with it.
+    let offset_start = if header.is_resource_db() { 6 } else { 0 };
+    let offsets: Vec<u64> = entries
+        .iter()
+        .map(|entry| {
+            let offset = &entry[offset_start..offset_start + 4];
+            u32::from_be_bytes([offset[0], offset[1], offset[2], offset[3]]) as u64
+        })
+        .collect();
+
+    let mut data_start = list_end;
+    for (name, offset) in [
+        ("AppInfo", header.app_info_id as u64),
+        ("SortInfo", header.sort_info_id as u64),
+    ] {
+        if offset == 0 {
+            continue;
+        }
+        if offset < list_end || offset > file_len {
+            findings.push(Finding::error(
+                None,
+                format!("{name} offset {offset} is outside the data area"),
+            ));
+        } else if data_start == list_end {
+            data_start = offset;
+        }
+    }
+
+    for (i, &offset) in offsets.iter().enumerate() {
+        let record = Some(i as u16);
+        if offset > file_len {
+            findings.push(Finding::error(
+                record,
+                format!("Offset {offset} is past the end of the file ({file_len} bytes)"),
+            ));
+        } else if offset < list_end {
+            findings.push(Finding::error(
+                record,
+                format!("Offset {offset} points into the header or record list"),
+            ));
+        }
+
+        match offsets.get(i + 1) {
+            Some(&next) if next < offset => findings.push(Finding::error(
+                record,
+                format!(
+                    "Offsets are not sorted, so the record overlaps record {}",
+                    i + 1
+                ),
+            )),
+            Some(&next) if next == offset => {
+                findings.push(Finding::warning(record, "Record is empty"))
+            }
+            _ => {}
+        }
+    }
+
+    if let Some(&first) = offsets.first() {
+        let gap = first.saturating_sub(data_start);
+        if header.app_info_id == 0 && header.sort_info_id == 0 && gap > RECORD_LIST_PADDING as u64 {
+            findings.push(Finding::warning(
+                None,
+                format!(
+                    "{gap} unused bytes between the record list and the first record, \
+                     number_of_records may be too small"
+                ),
+            ));
+        }
+    }
+
+    if !header.is_resource_db() {
+        let mut unique_ids = HashSet::new();
+        for (i, entry) in entries.iter().enumerate() {
+            let unique_id = u32::from_be_bytes([0, entry[5], entry[6], entry[7]]);
+            if !unique_ids.insert(unique_id) {
+                findings.push(Finding::warning(
+                    Some(i as u16),
+                    format!("Unique ID {unique_id} is used by an earlier record"),
+                ));
+            }
+        }
+    }
+
+    findings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::PDBBuilder;
+
+    fn test_bytes() -> Vec<u8> {
+        PDBBuilder::new()
+            .name("TestDB")
+            .type_("DATA")
+            .creator("TEST")
+            .add_record(0, 0, b"first")
+            .add_record(2, 0, b"second")
+            .add_record(4, 0, b"third")
+            .build()
+            .expect("Failed to build PDB")
+            .to_bytes()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_validate_clean() {
+        assert_eq!(validate(&test_bytes()), vec![]);
+    }
+
+    #[test]
+    fn test_validate_findings() {
+        let mut bytes = test_bytes();
+        // Swap the offsets of records 1 and 2 and give record 2 the ID of record 0.
+        let (first, second) = (bytes[86..90].to_vec(), bytes[94..98].to_vec());
+        bytes[86..90].copy_from_slice(&second);
+        bytes[94..98].copy_from_slice(&first);
+        bytes[99..102].copy_from_slice(&[0, 0, 0]);
+
+        let findings = validate(&bytes);
+        assert_eq!(findings.len(), 2);
+        assert_eq!(findings[0].severity, Severity::Error);
+        assert_eq!(findings[0].record, Some(1));
+        assert_eq!(findings[1].severity, Severity::Warning);
+        assert_eq!(findings[1].record, Some(2));
+
+        bytes[86..90].copy_from_slice(&0xFFFFu32.to_be_bytes());
+        assert!(
+            validate(&bytes).iter().any(
+                |finding| finding.record == Some(1) && finding.message.contains("past the end")
+            )
+        );
+
+        let findings = validate(&bytes[..90]);
+        assert_eq!(findings.len(), 1);
+        assert!(findings[0].message.contains("number_of_records"));
+    }
+}
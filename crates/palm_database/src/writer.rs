@@ -0,0 +1,141 @@
+use crate::{PDBError, PDBHeader, PDBRecord, RECORD_LIST_PADDING, RecordAttributes, file_offset};
+use std::io::{Read, Seek, SeekFrom, Write};
+
+/// Writes a database record by record instead of assembling it in memory.
+///
+/// The number of records must be known up front so that space for the record
+/// list can be reserved; the list itself is filled in by [`PDBWriter::finish`]
+/// once every record has been written and its offset is known.
+pub struct PDBWriter<W: Write + Seek> {
+    writer: W,
+    start: u64,
+    header: PDBHeader,
+    records: Vec<PDBRecord>,
+}
+
+impl<W: Write + Seek> PDBWriter<W> {
+    pub fn new(
+        mut writer: W,
+        mut header: PDBHeader,
+        number_of_records: u16,
+    ) -> Result<Self, PDBError> {
+        header.number_of_records = number_of_records;
+
+        let start = writer.stream_position()?;
+        writer.write_all(&header.to_bytes()?)?;
+        writer.write_all(&vec![0u8; number_of_records as usize * 8])?;
+        writer.write_all(&[0; RECORD_LIST_PADDING as usize])?;
+
+        Ok(Self {
+            writer,
+            start,
+            header,
+            records: Vec::with_capacity(number_of_records as usize),
+        })
+    }
+
+    fn begin_record(&mut self) -> Result<u16, PDBError> {
+        if self.records.len() >= self.header.number_of_records as usize {
+            return Err(PDBError::RecordCountMismatch {
+                expected: self.header.number_of_records,
+                written: self.records.len() + 1,
+            });
+        }
+
+        let data_offset = file_offset(self.writer.stream_position()? - self.start)?;
+        self.records.push(PDBRecord {
+            data_offset,
+            attributes: RecordAttributes::empty(),
+            unique_id: self.header.unique_id_seed,
+        });
+        self.header.unique_id_seed += 2;
+
+        Ok(self.records.len() as u16 - 1)
+    }
+
+    pub fn write_record(&mut self, data: &[u8]) -> Result<u16, PDBError> {
+        let index = self.begin_record()?;
+        self.writer.write_all(data)?;
+        Ok(index)
+    }
+
+    /// Copies a record from `reader`, e.g. an image file on disk, without
+    /// loading it into memory first.
+    pub fn write_record_from<R: Read>(&mut self, reader: &mut R) -> Result<u16, PDBError> {
+        let index = self.begin_record()?;
+        std::io::copy(reader, &mut self.writer)?;
+        Ok(index)
+    }
+
+    /// Fills in the record list and returns the underlying writer, positioned
+    /// at the end of the database.
+    pub fn finish(mut self) -> Result<W, PDBError> {
+        if self.records.len() != self.header.number_of_records as usize {
+            return Err(PDBError::RecordCountMismatch {
+                expected: self.header.number_of_records,
+                written: self.records.len(),
+            });
+        }
+
+        let end = self.writer.stream_position()?;
+        self.writer.seek(SeekFrom::Start(self.start))?;
+        self.writer.write_all(&self.header.to_bytes()?)?;
+        for record in &self.records {
+            self.writer
+                .write_all(&record.to_bytes(record.data_offset)?)?;
+        }
+        self.writer.seek(SeekFrom::Start(end))?;
+
+        Ok(self.writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PDB;
+    use crate::builder::PDBBuilder;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_pdb_writer() {
+        let pdb = PDBBuilder::new()
+            .name("TestDB")
+            .type_("TEST")
+            .creator("TEST")
+            .build()
+            .expect("Failed to build PDB");
+
+        let mut writer = PDBWriter::new(Cursor::new(Vec::new()), pdb.header, 2).unwrap();
+        writer.write_record(b"Record 1 data").unwrap();
+        writer
+            .write_record_from(&mut Cursor::new(b"Record 2 data"))
+            .unwrap();
+        let bytes = writer.finish().unwrap().into_inner();
+
+        let pdb = PDB::from_bytes(&mut Cursor::new(bytes)).expect("Failed to read PDB");
+        assert_eq!(pdb.header.number_of_records, 2);
+        assert_eq!(pdb.read_record(0).unwrap(), b"Record 1 data");
+        assert_eq!(pdb.read_record(1).unwrap(), b"Record 2 data");
+    }
+
+    #[test]
+    fn test_pdb_writer_record_count_mismatch() {
+        let pdb = PDBBuilder::new()
+            .name("TestDB")
+            .type_("TEST")
+            .creator("TEST")
+            .build()
+            .expect("Failed to build PDB");
+
+        let mut writer = PDBWriter::new(Cursor::new(Vec::new()), pdb.header, 2).unwrap();
+        writer.write_record(b"Record 1 data").unwrap();
+        assert!(matches!(
+            writer.finish(),
+            Err(PDBError::RecordCountMismatch {
+                expected: 2,
+                written: 1
+            })
+        ));
+    }
+}
@@ -0,0 +1,14 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use mobi::mobi::MOBI;
+use mobi::palmdoc::PalmDOC;
+use std::io::Cursor;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(mobi) = MOBI::from_bytes(&mut Cursor::new(data)) {
+        _ = mobi.html();
+        _ = mobi.guide();
+    }
+    _ = PalmDOC::from_bytes(&mut Cursor::new(data));
+});
@@ -0,0 +1,11 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use mobi::compression::{palmdoc_decompress, palmdoc_decompress_with_limit};
+
+fuzz_target!(|data: &[u8]| {
+    let out = palmdoc_decompress(data);
+    if let Ok(limited) = palmdoc_decompress_with_limit(data, 4096) {
+        assert_eq!(limited, out);
+    }
+});
@@ -0,0 +1,15 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use palm_database::PDB;
+use palm_database::pdb_ref::PdbRef;
+use palm_database::prc::PRC;
+use std::io::Cursor;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(pdb) = PDB::from_bytes(&mut Cursor::new(data)) {
+        _ = pdb.to_bytes();
+    }
+    _ = PdbRef::new(data);
+    _ = PRC::from_bytes(&mut Cursor::new(data));
+});